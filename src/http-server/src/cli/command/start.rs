@@ -1,71 +1,629 @@
-use std::net::IpAddr;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::path::PathBuf;
 use std::process::exit;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use clap::Parser;
 use tokio::runtime::Builder;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::config::Config;
+use crate::config::{
+    AccessLogConfig, AccessLogFormat, BasicAuth, CacheControl, CompressionConfig, Config,
+    ConfigFile, ConnectionConfig, ContentEncoding, CorsConfig, DEFAULT_HOST, DEFAULT_LOG_LEVEL,
+    DEFAULT_PORT, DEFAULT_SHUTDOWN_TIMEOUT, DenyAction, Http2Config, Listen, LogFormat,
+    MIN_MAX_HEAD_SIZE, RateLimitConfig, Route, Service, ServiceKind, SocketMode, TlsConfig,
+    Upstream, VirtualHost,
+};
+use crate::layer::cors::OriginPattern;
+use crate::layer::headers::HeaderRule;
+use crate::layer::ip_filter::{IpFilter, IpNetwork};
+use crate::logging;
 use crate::server::Server;
+use crate::systemd;
 
 const THREAD_NAME: &str = "http-server";
 
-#[derive(Clone, Debug, Parser)]
-pub enum Service {
-    FileServer,
-    FileExplorer,
+#[derive(Debug, Parser)]
+pub struct StartOpt {
+    /// Directory to serve files from, files uploaded through the file explorer
+    /// are written to it [default: ./]
+    #[clap(value_name = "PATH", env = "HTTP_SERVER_PATH")]
+    pub path: Option<PathBuf>,
+    /// Path to a TOML configuration file [default: ./config.toml if present]
+    #[clap(short = 'c', long, env = "HTTP_SERVER_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Host (IP) to bind the server [default: 0.0.0.0]
+    #[clap(long, env = "HTTP_SERVER_HOST")]
+    pub host: Option<IpAddr>,
+    /// Port to bind the server [default: 7878]
+    #[clap(short = 'p', long, env = "HTTP_SERVER_PORT")]
+    pub port: Option<u16>,
+    /// Address to listen on, either HOST:PORT or unix:PATH for a Unix domain
+    /// socket. Replaces `--host` and `--port`
+    #[clap(long, env = "HTTP_SERVER_LISTEN", value_name = "ADDRESS")]
+    pub listen: Option<Listen>,
+    /// Serve the listening socket inherited as file descriptor N, replaces
+    /// `--listen`. Sockets passed through systemd socket activation are used
+    /// automatically and take precedence over `--listen`
+    #[clap(long, env = "HTTP_SERVER_FD", value_name = "N")]
+    pub fd: Option<i32>,
+    /// Permissions of the Unix domain socket in octal notation, such as 660
+    #[clap(long, env = "HTTP_SERVER_SOCKET_MODE", value_name = "MODE")]
+    pub socket_mode: Option<SocketMode>,
+    /// Only accept clients from the provided IP networks, such as
+    /// `10.0.0.0/8,fd00::/8`
+    #[clap(
+        long,
+        env = "HTTP_SERVER_ALLOW",
+        value_name = "CIDRS",
+        value_delimiter = ','
+    )]
+    pub allow: Option<Vec<IpNetwork>>,
+    /// Reject clients from the provided IP networks
+    #[clap(
+        long,
+        env = "HTTP_SERVER_DENY",
+        value_name = "CIDRS",
+        value_delimiter = ','
+    )]
+    pub deny: Option<Vec<IpNetwork>>,
+    /// Response to rejected clients: forbid (403 Forbidden) or drop (close
+    /// the connection) [default: forbid]
+    #[clap(long, env = "HTTP_SERVER_DENY_ACTION", value_name = "ACTION")]
+    pub deny_action: Option<DenyAction>,
+    /// Proxies trusted to provide the client address through the
+    /// `X-Forwarded-For` header. Peers of a Unix domain socket are always
+    /// trusted
+    #[clap(
+        long,
+        env = "HTTP_SERVER_TRUSTED_PROXIES",
+        value_name = "CIDRS",
+        value_delimiter = ','
+    )]
+    pub trusted_proxies: Option<Vec<IpNetwork>>,
+    /// Maximum size of files uploaded through the file explorer, in bytes
+    #[clap(long, env = "HTTP_SERVER_MAX_UPLOAD_SIZE", value_name = "BYTES")]
+    pub max_upload_size: Option<u64>,
+    /// Requests per second allowed per client IP
+    #[clap(long, env = "HTTP_SERVER_RATE_LIMIT", value_name = "REQUESTS")]
+    pub rate_limit: Option<NonZeroU32>,
+    /// Requests a client can send at once before being rate limited
+    /// [default: --rate-limit]
+    #[clap(long, env = "HTTP_SERVER_RATE_LIMIT_BURST", value_name = "REQUESTS")]
+    pub rate_limit_burst: Option<NonZeroU32>,
+    /// Response bytes per second sent to each client IP
+    #[clap(long, env = "HTTP_SERVER_BANDWIDTH_LIMIT", value_name = "BYTES")]
+    pub bandwidth_limit: Option<NonZeroU64>,
+    /// Files being downloaded at once, by all clients
+    #[clap(long, env = "HTTP_SERVER_MAX_TRANSFERS", value_name = "TRANSFERS")]
+    pub max_transfers: Option<NonZeroUsize>,
+    /// Files being downloaded at once by each client IP
+    #[clap(
+        long,
+        env = "HTTP_SERVER_MAX_TRANSFERS_PER_IP",
+        value_name = "TRANSFERS"
+    )]
+    pub max_transfers_per_ip: Option<NonZeroUsize>,
+    /// Enable CORS, allowing every origin unless `--cors-origins` is
+    /// provided [default: false]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_CORS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub cors: Option<bool>,
+    /// Origins allowed to perform CORS requests, such as
+    /// `https://*.example.com`, `http://[::1]:8080` or regular expressions
+    /// prefixed with `regex:` [default: *]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_CORS_ORIGINS",
+        value_name = "ORIGINS",
+        value_delimiter = ','
+    )]
+    pub cors_origins: Option<Vec<OriginPattern>>,
+    /// Methods allowed in CORS requests [default: GET,POST]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_CORS_METHODS",
+        value_name = "METHODS",
+        value_delimiter = ','
+    )]
+    pub cors_methods: Option<Vec<String>>,
+    /// Headers allowed in CORS requests
+    #[clap(
+        long,
+        env = "HTTP_SERVER_CORS_HEADERS",
+        value_name = "HEADERS",
+        value_delimiter = ','
+    )]
+    pub cors_headers: Option<Vec<String>>,
+    /// Response headers exposed to scripts on CORS requests
+    #[clap(
+        long,
+        env = "HTTP_SERVER_CORS_EXPOSE_HEADERS",
+        value_name = "HEADERS",
+        value_delimiter = ','
+    )]
+    pub cors_expose_headers: Option<Vec<String>>,
+    /// Allow CORS requests with credentials [default: false]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_CORS_CREDENTIALS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub cors_credentials: Option<bool>,
+    /// Seconds CORS preflight responses can be cached for
+    #[clap(long, env = "HTTP_SERVER_CORS_MAX_AGE", value_name = "SECONDS")]
+    pub cors_max_age: Option<u64>,
+    /// Service to run [default: file-explorer]
+    #[clap(long, env = "HTTP_SERVER_SERVICE")]
+    pub service: Option<ServiceKind>,
+    /// Origin server the proxy service forwards requests to, such as
+    /// `http://127.0.0.1:3000`
+    #[clap(long, env = "HTTP_SERVER_UPSTREAM", value_name = "URL")]
+    pub upstream: Option<Upstream>,
+    /// Require HTTP Basic Authentication with the provided `username:password`
+    #[clap(long, env = "HTTP_SERVER_AUTH", value_name = "USERNAME:PASSWORD")]
+    pub auth: Option<BasicAuth>,
+    /// Serve over HTTPS using the provided certificate and key [default: false]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_TLS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub tls: Option<bool>,
+    /// Path to the PEM encoded TLS certificate (chain)
+    #[clap(long, env = "HTTP_SERVER_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// Path to the PEM encoded TLS private key (PKCS#8, RSA or EC)
+    #[clap(long, env = "HTTP_SERVER_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// Serve HTTP/2 alongside HTTP/1.1 [default: true]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_HTTP2",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub http2: Option<bool>,
+    /// Maximum number of concurrent HTTP/2 streams per connection [default: 200]
+    #[clap(long, env = "HTTP_SERVER_HTTP2_MAX_CONCURRENT_STREAMS")]
+    pub http2_max_concurrent_streams: Option<u32>,
+    /// Initial HTTP/2 stream-level flow control window size in bytes
+    #[clap(long, env = "HTTP_SERVER_HTTP2_STREAM_WINDOW_SIZE")]
+    pub http2_stream_window_size: Option<u32>,
+    /// Initial HTTP/2 connection-level flow control window size in bytes
+    #[clap(long, env = "HTTP_SERVER_HTTP2_CONNECTION_WINDOW_SIZE")]
+    pub http2_connection_window_size: Option<u32>,
+    /// Seconds allowed to send the head of a request, and to start one on an
+    /// inactive connection, 0 to disable [default: 30]
+    #[clap(long, env = "HTTP_SERVER_HEADER_READ_TIMEOUT", value_name = "SECONDS")]
+    pub header_read_timeout: Option<u64>,
+    /// Seconds after which inactive connections are closed, 0 to disable [default: 60]
+    #[clap(long, env = "HTTP_SERVER_IDLE_TIMEOUT", value_name = "SECONDS")]
+    pub idle_timeout: Option<u64>,
+    /// Seconds allowed to receive a request, including its body, and produce
+    /// its response. Disabled by default, as it also cuts off long uploads
+    #[clap(long, env = "HTTP_SERVER_REQUEST_TIMEOUT", value_name = "SECONDS")]
+    pub request_timeout: Option<u64>,
+    /// Maximum size of a request head in bytes, at least 8192
+    #[clap(long, env = "HTTP_SERVER_MAX_HEAD_SIZE", value_name = "BYTES")]
+    pub max_head_size: Option<usize>,
+    /// Maximum number of connections served at once
+    #[clap(long, env = "HTTP_SERVER_MAX_CONNECTIONS")]
+    pub max_connections: Option<NonZeroUsize>,
+    /// Compress responses with the encodings accepted by the client [default: false]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_COMPRESSION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub compression: Option<bool>,
+    /// Encodings used to compress responses [default: gzip,br,zstd]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_COMPRESSION_ENCODINGS",
+        value_name = "ENCODINGS",
+        value_delimiter = ','
+    )]
+    pub compression_encodings: Option<Vec<ContentEncoding>>,
+    /// Minimum response size in bytes for compression to apply [default: 1024]
+    #[clap(long, env = "HTTP_SERVER_COMPRESSION_MIN_SIZE", value_name = "BYTES")]
+    pub compression_min_size: Option<u64>,
+    /// Add security headers such as `Content-Security-Policy` and, under TLS,
+    /// `Strict-Transport-Security` to responses [default: false]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_SECURE_HEADERS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub secure_headers: Option<bool>,
+    /// Serve files with `Cache-Control: no-cache`, ignoring configured
+    /// Cache-Control rules [default: false]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_NO_CACHE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub no_cache: Option<bool>,
+    /// Log every request [default: false, true when --access-log-file is provided]
+    #[clap(
+        long,
+        env = "HTTP_SERVER_ACCESS_LOG",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub access_log: Option<bool>,
+    /// Format of access log entries: common, combined or json [default: combined]
+    #[clap(long, env = "HTTP_SERVER_ACCESS_LOG_FORMAT", value_name = "FORMAT")]
+    pub access_log_format: Option<AccessLogFormat>,
+    /// File access log entries are appended to, reopened on SIGHUP
+    /// [default: stdout]
+    #[clap(long, env = "HTTP_SERVER_ACCESS_LOG_FILE", value_name = "PATH")]
+    pub access_log_file: Option<PathBuf>,
+    /// Filter for log events, such as `debug` or `http_server=debug,warn`.
+    /// Overrides `RUST_LOG` [default: info]
+    #[clap(long, env = "HTTP_SERVER_LOG_LEVEL", value_name = "FILTER")]
+    pub log_level: Option<String>,
+    /// Only log errors, overrides `--log-level` and `RUST_LOG` [default: false]
+    #[clap(
+        short = 'q',
+        long,
+        env = "HTTP_SERVER_QUIET",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub quiet: Option<bool>,
+    /// Format of log events: text, json or compact [default: text]
+    #[clap(long, env = "HTTP_SERVER_LOG_FORMAT", value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+    /// Seconds to wait for active connections to complete on shutdown [default: 30]
+    #[clap(long, env = "HTTP_SERVER_SHUTDOWN_TIMEOUT", value_name = "SECONDS")]
+    pub shutdown_timeout: Option<u64>,
+    /// Mount a service under a path prefix, can be repeated. Cannot be used
+    /// along with `PATH`, `--service` or `--upstream`, as each route sets its
+    /// own. The file explorer can only be mounted at `/`
+    #[clap(
+        long = "route",
+        env = "HTTP_SERVER_ROUTES",
+        value_name = "PREFIX=SERVICE[:PATH]",
+        value_delimiter = ',',
+        conflicts_with_all = ["path", "service", "upstream"]
+    )]
+    pub routes: Vec<Route>,
 }
 
-impl FromStr for Service {
-    type Err = String;
+impl StartOpt {
+    /// Builds the `Config` by layering sources with the following precedence
+    /// (highest first): CLI flags, environment variables, configuration file
+    /// and defaults.
+    ///
+    /// CLI flags and environment variables are resolved by `clap`, so only
+    /// values missing from both are read from the configuration file.
+    pub fn resolve_config(&self, file: &ConfigFile) -> Result<Config> {
+        let kind = self
+            .service
+            .or(file.service)
+            .unwrap_or(ServiceKind::FileExplorer);
+        let routes = self.resolve_routes(file, kind)?;
+        let virtual_hosts = self.resolve_virtual_hosts(file)?;
+
+        let tls = if self.tls.or(file.tls).unwrap_or(false) {
+            Some(TlsConfig {
+                cert: self
+                    .tls_cert
+                    .clone()
+                    .or(file.tls_cert.clone())
+                    .context("TLS is enabled but no certificate was provided (--tls-cert)")?,
+                key: self
+                    .tls_key
+                    .clone()
+                    .or(file.tls_key.clone())
+                    .context("TLS is enabled but no private key was provided (--tls-key)")?,
+            })
+        } else {
+            None
+        };
 
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s {
-            "file-server" => Ok(Service::FileServer),
-            "file-explorer" => Ok(Service::FileExplorer),
-            _ => Err(format!("Invalid service: {}", s)),
+        let http2_file = file.http2.clone().unwrap_or_default();
+        let http2 = Http2Config {
+            enabled: self.http2.or(http2_file.enabled),
+            max_concurrent_streams: self
+                .http2_max_concurrent_streams
+                .or(http2_file.max_concurrent_streams),
+            initial_stream_window_size: self
+                .http2_stream_window_size
+                .or(http2_file.initial_stream_window_size),
+            initial_connection_window_size: self
+                .http2_connection_window_size
+                .or(http2_file.initial_connection_window_size),
+        };
+
+        http2.validate()?;
+
+        let connection_file = file.connection.clone().unwrap_or_default();
+        let connection = ConnectionConfig {
+            header_read_timeout: self
+                .header_read_timeout
+                .or(connection_file.header_read_timeout),
+            idle_timeout: self.idle_timeout.or(connection_file.idle_timeout),
+            request_timeout: self.request_timeout.or(connection_file.request_timeout),
+            max_head_size: self.max_head_size.or(connection_file.max_head_size),
+            max_connections: self.max_connections.or(connection_file.max_connections),
+        };
+
+        if let Some(size) = connection.max_head_size
+            && size < MIN_MAX_HEAD_SIZE
+        {
+            bail!("The maximum request head size must be at least {MIN_MAX_HEAD_SIZE} bytes.");
         }
+
+        let compression_file = file.compression.clone().unwrap_or_default();
+        let compression = CompressionConfig {
+            enabled: self.compression.or(compression_file.enabled),
+            encodings: self
+                .compression_encodings
+                .clone()
+                .or(compression_file.encodings),
+            min_size: self.compression_min_size.or(compression_file.min_size),
+        };
+
+        let cache_control = CacheControl {
+            no_cache: self.no_cache.or(file.no_cache).unwrap_or(false),
+            rules: file.cache_control.clone().unwrap_or_default(),
+        };
+
+        let access_log_file = file.access_log.clone().unwrap_or_default();
+        let access_log = AccessLogConfig {
+            enabled: self.access_log.or(access_log_file.enabled),
+            format: self.access_log_format.or(access_log_file.format),
+            path: self.access_log_file.clone().or(access_log_file.path),
+        };
+
+        let header_rules = file
+            .headers
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(HeaderRule::try_from)
+            .collect::<Result<Vec<_>>>()?;
+
+        let ip_filter = IpFilter {
+            allow: self
+                .allow
+                .clone()
+                .or(file.allow.clone())
+                .unwrap_or_default(),
+            deny: self.deny.clone().or(file.deny.clone()).unwrap_or_default(),
+            methods: Vec::new(),
+        };
+
+        let rate_limit_file = file.rate_limit.clone().unwrap_or_default();
+        let rate_limit = RateLimitConfig {
+            requests_per_second: self.rate_limit.or(rate_limit_file.requests_per_second),
+            burst: self.rate_limit_burst.or(rate_limit_file.burst),
+            bytes_per_second: self.bandwidth_limit.or(rate_limit_file.bytes_per_second),
+            max_transfers: self.max_transfers.or(rate_limit_file.max_transfers),
+            max_transfers_per_ip: self
+                .max_transfers_per_ip
+                .or(rate_limit_file.max_transfers_per_ip),
+        };
+
+        let cors_file = file.cors.clone().unwrap_or_default();
+        let cors = CorsConfig {
+            enabled: self.cors.or(cors_file.enabled),
+            allow_origins: self.cors_origins.clone().or(cors_file.allow_origins),
+            allow_methods: self.cors_methods.clone().or(cors_file.allow_methods),
+            allow_headers: self.cors_headers.clone().or(cors_file.allow_headers),
+            expose_headers: self
+                .cors_expose_headers
+                .clone()
+                .or(cors_file.expose_headers),
+            allow_credentials: self.cors_credentials.or(cors_file.allow_credentials),
+            max_age: self.cors_max_age.or(cors_file.max_age),
+        };
+
+        let listen = self.resolve_listen(file, systemd::listen_fd());
+
+        Ok(Config {
+            listen,
+            socket_mode: self.socket_mode.or(file.socket_mode),
+            ip_filter,
+            deny_action: self.deny_action.or(file.deny_action).unwrap_or_default(),
+            trusted_proxies: self
+                .trusted_proxies
+                .clone()
+                .or(file.trusted_proxies.clone())
+                .unwrap_or_default(),
+            rate_limit,
+            max_upload_size: self.max_upload_size.or(file.max_upload_size),
+            cors,
+            header_rules,
+            secure_headers: self.secure_headers.or(file.secure_headers).unwrap_or(false),
+            routes,
+            virtual_hosts,
+            tls,
+            http2,
+            connection,
+            compression,
+            cache_control,
+            access_log,
+            shutdown_timeout: self
+                .shutdown_timeout
+                .or(file.shutdown_timeout)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+        })
     }
-}
 
-#[derive(Debug, Parser)]
-pub struct StartOpt {
-    /// Host (IP) to bind the server
-    #[clap(long, default_value = "0.0.0.0")]
-    pub host: IpAddr,
-    /// Port to bind the server
-    #[clap(short = 'p', long, default_value = "7878")]
-    pub port: u16,
-    /// Enable CORS with a permissive policy
-    #[clap(long, default_value = "false")]
-    pub cors: bool,
-    /// Service to run
-    #[clap(long, default_value = "file-explorer")]
-    pub service: Service,
-}
+    /// Resolves where the server listens, logging which source was used.
+    /// A socket passed by systemd takes precedence over the configured
+    /// address, but not over `--fd`.
+    fn resolve_listen(&self, file: &ConfigFile, systemd_fd: Option<i32>) -> Listen {
+        let configured = self.listen.is_some()
+            || file.listen.is_some()
+            || self.host.or(file.host).is_some()
+            || self.port.or(file.port).is_some();
+
+        if let Some(fd) = self.fd {
+            info!(fd, "Listening on the inherited socket passed through --fd");
 
-impl From<&StartOpt> for Config {
-    fn from(val: &StartOpt) -> Self {
-        Config {
-            host: val.host,
-            port: val.port,
-            cors: val.cors,
-            service: val.service.clone().into(),
+            return Listen::Fd(fd);
         }
+
+        if let Some(fd) = systemd_fd {
+            if configured {
+                warn!(
+                    fd,
+                    "Ignoring the configured address in favor of the socket passed by systemd"
+                );
+            } else {
+                info!(fd, "Listening on the socket passed by systemd");
+            }
+
+            return Listen::Fd(fd);
+        }
+
+        self.listen
+            .clone()
+            .or_else(|| file.listen.clone())
+            .unwrap_or_else(|| {
+                Listen::Tcp(SocketAddr::new(
+                    self.host.or(file.host).unwrap_or(DEFAULT_HOST),
+                    self.port.or(file.port).unwrap_or(DEFAULT_PORT),
+                ))
+            })
+    }
+
+    /// Resolves the filter and format of log events, which are set up before
+    /// the rest of the configuration so it can be reported.
+    fn resolve_logging(&self, file: &ConfigFile) -> (String, LogFormat) {
+        let log_level = if self.quiet.unwrap_or(false) {
+            String::from("error")
+        } else {
+            self.log_level
+                .clone()
+                .or_else(|| {
+                    env::var("RUST_LOG")
+                        .ok()
+                        .filter(|filter| !filter.is_empty())
+                })
+                .or(file.log_level.clone())
+                .unwrap_or_else(|| String::from(DEFAULT_LOG_LEVEL))
+        };
+
+        (
+            log_level,
+            self.log_format.or(file.log_format).unwrap_or_default(),
+        )
+    }
+
+    /// Resolves the routing table. Routes from the CLI replace routes from the
+    /// configuration file, and when no routes are configured the selected
+    /// service is mounted at `/`.
+    ///
+    /// The `--auth` credentials apply to every route without its own.
+    fn resolve_routes(&self, file: &ConfigFile, kind: ServiceKind) -> Result<Vec<Route>> {
+        let routes = if !self.routes.is_empty() {
+            self.routes.clone()
+        } else if let Some(routes) = file.routes.clone() {
+            routes
+                .into_iter()
+                .map(Route::try_from)
+                .collect::<Result<_>>()?
+        } else {
+            let section = file.section(kind);
+            let root_directory = self
+                .path
+                .clone()
+                .or_else(|| section.and_then(|section| section.path.clone()));
+            let upstream = self
+                .upstream
+                .clone()
+                .or_else(|| section.and_then(|section| section.upstream.clone()));
+            let basic_auth = self
+                .auth
+                .clone()
+                .or_else(|| section.and_then(|section| section.basic_auth.clone()));
+
+            vec![Route {
+                prefix: String::from("/"),
+                service: Service::new(kind, root_directory, upstream, basic_auth)?,
+                ip_filter: IpFilter::default(),
+                rate_limit: RateLimitConfig::default(),
+                max_upload_size: None,
+            }]
+        };
+
+        routes
+            .into_iter()
+            .map(|route| {
+                route.validate()?;
+
+                Ok(Route {
+                    prefix: route.prefix,
+                    service: route.service.with_default_basic_auth(self.auth.as_ref()),
+                    ip_filter: route.ip_filter,
+                    rate_limit: route.rate_limit,
+                    max_upload_size: route.max_upload_size,
+                })
+            })
+            .collect()
+    }
+
+    /// Resolves the virtual hosts from the configuration file. Requests not
+    /// matching any of them are served by the routing table.
+    fn resolve_virtual_hosts(&self, file: &ConfigFile) -> Result<Vec<VirtualHost>> {
+        file.virtual_hosts
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|section| {
+                let mut virtual_host = VirtualHost::try_from(section)?;
+
+                virtual_host.service.validate()?;
+                virtual_host.service = virtual_host
+                    .service
+                    .with_default_basic_auth(self.auth.as_ref());
+
+                Ok(virtual_host)
+            })
+            .collect()
     }
-}
 
-impl StartOpt {
     pub fn exec(&self) -> Result<()> {
         let rt = Builder::new_multi_thread()
             .enable_all()
             .thread_name(THREAD_NAME)
             .build()?;
         let rt = Arc::new(rt);
-        let config: Config = self.into();
+        let file = ConfigFile::discover(self.config.as_deref())?;
+        let (log_level, log_format) = self.resolve_logging(&file);
+
+        logging::init(&log_level, log_format)?;
+
+        let config = self.resolve_config(&file)?;
+
         let server = Server::new(config);
 
         rt.block_on(async {
@@ -82,3 +640,76 @@ impl StartOpt {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    use clap::Parser;
+
+    use super::StartOpt;
+    use crate::config::{ConfigFile, Listen};
+
+    fn resolve_listen(args: &[&str], file: &str, systemd_fd: Option<i32>) -> Listen {
+        let opt = StartOpt::try_parse_from([&["start"], args].concat()).unwrap();
+        let file = ConfigFile::from_str(file).unwrap();
+
+        opt.resolve_listen(&file, systemd_fd)
+    }
+
+    #[test]
+    fn resolves_listen_sources() {
+        assert_eq!(
+            resolve_listen(&[], "", None),
+            Listen::Tcp(SocketAddr::from(([0, 0, 0, 0], 7878)))
+        );
+        assert_eq!(
+            resolve_listen(&["--port", "8080"], "host = \"0.0.0.0\"", None),
+            Listen::Tcp(SocketAddr::from(([0, 0, 0, 0], 8080)))
+        );
+        assert_eq!(
+            resolve_listen(&[], "listen = \"unix:/run/http-server.sock\"", None),
+            Listen::Unix(PathBuf::from("/run/http-server.sock"))
+        );
+        assert_eq!(
+            resolve_listen(
+                &["--listen", "127.0.0.1:80"],
+                "listen = \"unix:/run/a.sock\"",
+                None
+            ),
+            Listen::Tcp(SocketAddr::from(([127, 0, 0, 1], 80)))
+        );
+    }
+
+    #[test]
+    fn prefers_inherited_sockets() {
+        assert_eq!(
+            resolve_listen(&["--listen", "127.0.0.1:80"], "", Some(3)),
+            Listen::Fd(3)
+        );
+        assert_eq!(
+            resolve_listen(&["--fd", "5"], "port = 80", Some(3)),
+            Listen::Fd(5)
+        );
+    }
+
+    #[test]
+    fn rejects_service_options_with_routes() {
+        let parse = |args: &[&str]| StartOpt::try_parse_from([&["start"], args].concat());
+
+        assert!(parse(&["--route", "/static=file-server:./public"]).is_ok());
+        assert!(parse(&["--route", "/static=file-server", "./public"]).is_err());
+        assert!(parse(&["--route", "/=file-explorer", "--service", "file-server"]).is_err());
+        assert!(
+            parse(&[
+                "--route",
+                "/api=proxy:http://127.0.0.1:3000",
+                "--upstream",
+                "http://127.0.0.1:4000"
+            ])
+            .is_err()
+        );
+    }
+}
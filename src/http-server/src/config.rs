@@ -1,48 +1,741 @@
-use std::net::IpAddr;
+use std::collections::BTreeMap;
+use std::fs::{read_dir, read_to_string};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::{Error, Result, bail};
+use anyhow::{Context, Error, Result, bail};
+use globset::Glob;
+use http::uri::Scheme;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri};
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::handler::file_server::CacheControlDirective;
+use crate::handler::virtual_host::HostPattern;
+use crate::layer::cors::OriginPattern;
+use crate::layer::headers::HeaderRule;
+use crate::layer::ip_filter::{IpFilter, IpNetwork};
+
+/// Default IP address to bind to when not provided by any configuration source.
+pub const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+
+/// Default port to bind to when not provided by any configuration source.
+pub const DEFAULT_PORT: u16 = 7878;
+
+/// Configuration file looked up in the current working directory when no
+/// `--config` option is provided.
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Default time to wait for active connections to complete on shutdown.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time allowed to clients to send the head of a request.
+pub const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time after which connections without any activity are closed.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Smallest request head size limit supported, in bytes.
+pub const MIN_MAX_HEAD_SIZE: usize = 8192;
+
+/// Largest HTTP/2 flow control window size allowed by RFC 9113, in bytes.
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+/// Default minimum response size, in bytes, for compression to apply.
+pub const DEFAULT_COMPRESSION_MIN_SIZE: u64 = 1024;
+
+/// Default filter for log events, using the same syntax as `RUST_LOG`.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Default directory to serve files from.
+pub const DEFAULT_ROOT_DIRECTORY: &str = "./";
 
 #[derive(Clone, Debug)]
 pub enum Service {
     FileServer {
-        root_directory: String,
+        root_directory: PathBuf,
         basic_auth: Option<BasicAuth>,
     },
     FileExplorer {
-        root_directory: String,
+        root_directory: PathBuf,
+        basic_auth: Option<BasicAuth>,
+    },
+    Proxy {
+        upstream: Upstream,
         basic_auth: Option<BasicAuth>,
     },
 }
 
-impl From<crate::cli::command::start::Service> for Service {
-    fn from(val: crate::cli::command::start::Service) -> Self {
-        match val {
-            crate::cli::command::start::Service::FileServer => Service::FileServer {
-                root_directory: "./".into(),
-                basic_auth: None,
+impl Service {
+    /// Creates a `Service` of the provided `kind`. File services serve files
+    /// from `path`, or the current directory if not provided, whereas the
+    /// proxy service requires an `upstream`.
+    pub fn new(
+        kind: ServiceKind,
+        path: Option<PathBuf>,
+        upstream: Option<Upstream>,
+        basic_auth: Option<BasicAuth>,
+    ) -> Result<Self> {
+        let root_directory = || path.unwrap_or_else(|| PathBuf::from(DEFAULT_ROOT_DIRECTORY));
+
+        Ok(match kind {
+            ServiceKind::FileServer => Service::FileServer {
+                root_directory: root_directory(),
+                basic_auth,
+            },
+            ServiceKind::FileExplorer => Service::FileExplorer {
+                root_directory: root_directory(),
+                basic_auth,
             },
-            crate::cli::command::start::Service::FileExplorer => Service::FileExplorer {
-                root_directory: "./".into(),
-                basic_auth: None,
+            ServiceKind::Proxy => Service::Proxy {
+                upstream: upstream.context("The proxy service requires an upstream.")?,
+                basic_auth,
             },
+        })
+    }
+
+    /// Directory files are served from, `None` for the proxy service.
+    pub fn root_directory(&self) -> Option<&Path> {
+        match self {
+            Service::FileServer { root_directory, .. }
+            | Service::FileExplorer { root_directory, .. } => Some(root_directory),
+            Service::Proxy { .. } => None,
+        }
+    }
+
+    pub fn basic_auth(&self) -> Option<&BasicAuth> {
+        match self {
+            Service::FileServer { basic_auth, .. }
+            | Service::FileExplorer { basic_auth, .. }
+            | Service::Proxy { basic_auth, .. } => basic_auth.as_ref(),
+        }
+    }
+
+    /// Sets `basic_auth` unless the service already has credentials.
+    pub fn with_default_basic_auth(mut self, default: Option<&BasicAuth>) -> Self {
+        let (Service::FileServer { basic_auth, .. }
+        | Service::FileExplorer { basic_auth, .. }
+        | Service::Proxy { basic_auth, .. }) = &mut self;
+
+        if basic_auth.is_none() {
+            *basic_auth = default.cloned();
+        }
+
+        self
+    }
+
+    /// Ensures the root directory of file services exists and is readable.
+    pub fn validate(&self) -> Result<()> {
+        match self.root_directory() {
+            Some(root_directory) => validate_root_directory(root_directory),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Origin server the proxy service forwards requests to, such as
+/// `http://127.0.0.1:3000`.
+///
+/// The upstream path, if any, is prepended to the path of forwarded
+/// requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Upstream(Uri);
+
+impl Upstream {
+    pub fn uri(&self) -> &Uri {
+        &self.0
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let uri = Uri::from_str(s).with_context(|| format!("Invalid upstream \"{s}\"."))?;
+
+        if uri.scheme() != Some(&Scheme::HTTP) {
+            bail!("Invalid upstream \"{s}\", only \"http://\" upstreams are supported.");
+        }
+
+        if uri.authority().is_none() || uri.query().is_some() {
+            bail!(
+                "Invalid upstream \"{s}\", expected an origin such as \"http://127.0.0.1:3000\"."
+            );
         }
+
+        Ok(Upstream(uri))
+    }
+}
+
+impl TryFrom<String> for Upstream {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::from_str(&value)
     }
 }
 
+/// A `Service` mounted under a path prefix.
+///
+/// The prefix is stripped from the request path before it reaches the
+/// service, so a file server mounted at `/static` resolves `/static/app.js`
+/// as `/app.js` relative to its root directory.
 #[derive(Clone, Debug)]
-pub struct Config {
-    /// The IP address to bind to.
-    pub host: IpAddr,
-    /// The port to bind to.
-    pub port: u16,
-    /// Enable CORS with a permissive policy.
-    pub cors: bool,
-    /// Service
+pub struct Route {
+    pub prefix: String,
+    pub service: Service,
+    /// Client addresses allowed to access the route
+    pub ip_filter: IpFilter,
+    /// Limits applied to requests to the route, in addition to the global
+    /// limits
+    pub rate_limit: RateLimitConfig,
+    /// Maximum size of uploaded files in bytes, overriding the global limit
+    pub max_upload_size: Option<u64>,
+}
+
+impl Route {
+    /// Ensures the service can be mounted at the route prefix.
+    ///
+    /// The file explorer UI requests its assets and API from `/`, so it can
+    /// only be mounted at the root.
+    pub fn validate(&self) -> Result<()> {
+        if matches!(self.service, Service::FileExplorer { .. })
+            && !self.prefix.trim_matches('/').is_empty()
+        {
+            bail!(
+                "The file explorer can only be mounted at \"/\", not at \"{}\".",
+                self.prefix
+            );
+        }
+
+        self.service.validate()
+    }
+}
+
+impl FromStr for Route {
+    type Err = Error;
+
+    /// Parses routes in the `PREFIX=SERVICE[:PATH]` form, for instance
+    /// `/static=file-server:./public`. For the proxy service `PATH` is the
+    /// upstream, as in `/api=proxy:http://127.0.0.1:3000`.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((prefix, service)) = s.split_once('=') else {
+            bail!("Expected a route in the form PREFIX=SERVICE[:PATH], got \"{s}\".");
+        };
+        let (kind, target) = match service.split_once(':') {
+            Some((kind, target)) => (kind, Some(target)),
+            None => (service, None),
+        };
+        let kind = ServiceKind::from_str(kind).map_err(Error::msg)?;
+        let service = match kind {
+            ServiceKind::Proxy => Service::new(
+                kind,
+                None,
+                target.map(Upstream::from_str).transpose()?,
+                None,
+            )?,
+            _ => Service::new(kind, target.map(PathBuf::from), None, None)?,
+        };
+
+        Ok(Route {
+            prefix: prefix.to_string(),
+            service,
+            ip_filter: IpFilter::default(),
+            rate_limit: RateLimitConfig::default(),
+            max_upload_size: None,
+        })
+    }
+}
+
+/// Address the server listens on, either a TCP socket address such as
+/// `127.0.0.1:7878` or a Unix domain socket path prefixed with `unix:`, as in
+/// `unix:/run/http-server.sock`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    /// Listening socket inherited from the parent process, such as systemd
+    Fd(i32),
+}
+
+impl FromStr for Listen {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                bail!("Missing Unix domain socket path in \"{s}\".");
+            }
+
+            return Ok(Listen::Unix(PathBuf::from(path)));
+        }
+
+        SocketAddr::from_str(s)
+            .map(Listen::Tcp)
+            .with_context(|| format!("Expected HOST:PORT or unix:PATH, got \"{s}\"."))
+    }
+}
+
+impl TryFrom<String> for Listen {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::from_str(&value)
+    }
+}
+
+/// Permissions of a Unix domain socket in octal notation, such as `660`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct SocketMode(pub u32);
+
+impl FromStr for SocketMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        u32::from_str_radix(s, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .map(SocketMode)
+            .with_context(|| format!("Invalid socket mode \"{s}\", expected octal permissions."))
+    }
+}
+
+impl TryFrom<String> for SocketMode {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::from_str(&value)
+    }
+}
+
+/// Limits on the requests and transfers of each client IP address. Clients
+/// exceeding them are answered with `429 Too Many Requests`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RateLimitConfig {
+    /// Requests per second allowed per client.
+    pub requests_per_second: Option<NonZeroU32>,
+    /// Requests a client can send at once before being limited to
+    /// `requests_per_second`, defaults to `requests_per_second`.
+    pub burst: Option<NonZeroU32>,
+    /// Response bytes per second sent to each client, responses are slowed
+    /// down rather than rejected.
+    pub bytes_per_second: Option<NonZeroU64>,
+    /// Files being downloaded at once, by all clients.
+    pub max_transfers: Option<NonZeroUsize>,
+    /// Files being downloaded at once by each client.
+    pub max_transfers_per_ip: Option<NonZeroUsize>,
+}
+
+impl RateLimitConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.requests_per_second.is_some()
+            || self.bytes_per_second.is_some()
+            || self.max_transfers.is_some()
+            || self.max_transfers_per_ip.is_some()
+    }
+}
+
+/// Response to clients rejected by the IP filter.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DenyAction {
+    /// Respond with `403 Forbidden`
+    #[default]
+    Forbid,
+    /// Close the connection as soon as it is accepted. Requests forwarded by
+    /// trusted proxies are answered with `403 Forbidden` instead.
+    Drop,
+}
+
+impl FromStr for DenyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "forbid" => Ok(DenyAction::Forbid),
+            "drop" => Ok(DenyAction::Drop),
+            _ => Err(format!("Invalid deny action: {}", s)),
+        }
+    }
+}
+
+/// A `Service` selected by the host name a request is addressed to.
+#[derive(Clone, Debug)]
+pub struct VirtualHost {
+    /// Host names served by this virtual host
+    pub hosts: Vec<HostPattern>,
     pub service: Service,
+    /// Headers added to every response
+    pub headers: HeaderMap,
+    /// Certificate presented to clients requesting one of `hosts` through
+    /// SNI, when TLS is enabled.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The address to listen on.
+    pub listen: Listen,
+    /// Permissions of the socket file when listening on a Unix domain
+    /// socket.
+    pub socket_mode: Option<SocketMode>,
+    /// Client addresses allowed to access the server.
+    pub ip_filter: IpFilter,
+    /// Response to clients rejected by `ip_filter`.
+    pub deny_action: DenyAction,
+    /// Proxies trusted to provide the client address through the
+    /// `X-Forwarded-For` header. Peers of a Unix domain socket are always
+    /// trusted.
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// Limits on the requests and transfers of each client.
+    pub rate_limit: RateLimitConfig,
+    /// Maximum size of files uploaded through the file explorer, in bytes.
+    pub max_upload_size: Option<u64>,
+    /// Cross-Origin Resource Sharing settings.
+    pub cors: CorsConfig,
+    /// Rules modifying the headers of responses.
+    pub header_rules: Vec<HeaderRule>,
+    /// Add security headers such as `Content-Security-Policy` to responses.
+    pub secure_headers: bool,
+    /// Services mounted by path prefix, used for requests not matching any
+    /// virtual host.
+    pub routes: Vec<Route>,
+    /// Services selected by host name
+    pub virtual_hosts: Vec<VirtualHost>,
+    /// TLS certificate and key, when TLS is enabled.
+    pub tls: Option<TlsConfig>,
+    /// HTTP/2 connection settings.
+    pub http2: Http2Config,
+    /// Timeouts and limits of client connections.
+    pub connection: ConnectionConfig,
+    /// Response compression settings.
+    pub compression: CompressionConfig,
+    /// `Cache-Control` policy of files served by the file server.
+    pub cache_control: CacheControl,
+    /// Access log settings.
+    pub access_log: AccessLogConfig,
+    /// Time to wait for active connections to complete on shutdown.
+    pub shutdown_timeout: Duration,
+}
+
+/// Timeouts and limits protecting the server from clients holding connections
+/// open. Timeouts are in seconds, `0` disables them.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConnectionConfig {
+    /// Time allowed to send the head of a request, including the TLS
+    /// handshake. Connections without any request in progress are closed
+    /// once no request has started for this time, for HTTP/1 and HTTP/2.
+    pub header_read_timeout: Option<u64>,
+    /// Time after which connections without requests in progress nor any data
+    /// sent or received are closed.
+    pub idle_timeout: Option<u64>,
+    /// Time allowed to receive a request, including its body, and produce
+    /// its response. Requests taking longer are answered with
+    /// `408 Request Timeout`. Disabled unless set, as it also cuts off long
+    /// uploads and downloads on healthy connections.
+    pub request_timeout: Option<u64>,
+    /// Maximum size of a request head, in bytes. Unset falls back to hyper's
+    /// default.
+    pub max_head_size: Option<usize>,
+    /// Maximum number of connections served at once, further connections wait
+    /// to be accepted until others are closed.
+    pub max_connections: Option<NonZeroUsize>,
+}
+
+impl ConnectionConfig {
+    pub fn header_read_timeout(&self) -> Option<Duration> {
+        timeout(self.header_read_timeout, DEFAULT_HEADER_READ_TIMEOUT)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        timeout(self.idle_timeout, DEFAULT_IDLE_TIMEOUT)
+    }
+
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    }
+}
+
+/// Resolves a timeout set in seconds, where `0` disables it.
+fn timeout(secs: Option<u64>, default: Duration) -> Option<Duration> {
+    match secs {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => Some(default),
+    }
+}
+
+/// HTTP/2 settings. Unset values fall back to hyper's defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Http2Config {
+    /// Serve HTTP/2 alongside HTTP/1.1. HTTP/2 is negotiated through ALPN
+    /// under TLS and with prior knowledge (h2c) on cleartext connections.
+    pub enabled: Option<bool>,
+    /// Maximum number of concurrent streams per connection.
+    pub max_concurrent_streams: Option<u32>,
+    /// Initial stream-level flow control window size, in bytes.
+    pub initial_stream_window_size: Option<u32>,
+    /// Initial connection-level flow control window size, in bytes.
+    pub initial_connection_window_size: Option<u32>,
+}
+
+impl Http2Config {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// Ensures flow control window sizes are within the range allowed by
+    /// HTTP/2.
+    pub fn validate(&self) -> Result<()> {
+        for (name, size) in [
+            ("stream", self.initial_stream_window_size),
+            ("connection", self.initial_connection_window_size),
+        ] {
+            if size.is_some_and(|size| size > MAX_WINDOW_SIZE) {
+                bail!("The HTTP/2 {name} window size must be at most {MAX_WINDOW_SIZE} bytes.");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Response compression settings, compression is negotiated with clients
+/// through the `Accept-Encoding` header.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CompressionConfig {
+    /// Compress responses when supported by the client.
+    pub enabled: Option<bool>,
+    /// Encodings offered to clients, all of them by default.
+    pub encodings: Option<Vec<ContentEncoding>>,
+    /// Minimum response size, in bytes, for compression to apply.
+    pub min_size: Option<u64>,
+}
+
+impl CompressionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    /// Checks whether `encoding` is enabled.
+    pub fn is_offered(&self, encoding: ContentEncoding) -> bool {
+        self.is_enabled()
+            && self
+                .encodings
+                .as_ref()
+                .is_none_or(|encodings| encodings.contains(&encoding))
+    }
+
+    pub fn min_size(&self) -> u64 {
+        self.min_size.unwrap_or(DEFAULT_COMPRESSION_MIN_SIZE)
+    }
+}
+
+/// Cross-Origin Resource Sharing settings, read either from the `[cors]`
+/// table or from `cors = true` to allow every origin.
+///
+/// Lists accept `*` to allow any value, which is not allowed along with
+/// credentials.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CorsConfig {
+    /// Answer CORS requests. Enabled by default when `allow-origins` is
+    /// provided.
+    pub enabled: Option<bool>,
+    /// Origins allowed to perform requests, every origin by default.
+    pub allow_origins: Option<Vec<OriginPattern>>,
+    /// Methods allowed in requests, `GET` and `POST` by default.
+    pub allow_methods: Option<Vec<String>>,
+    /// Headers allowed in requests.
+    pub allow_headers: Option<Vec<String>>,
+    /// Response headers exposed to scripts.
+    pub expose_headers: Option<Vec<String>>,
+    /// Allow requests with credentials such as cookies.
+    pub allow_credentials: Option<bool>,
+    /// Seconds preflight responses can be cached for.
+    pub max_age: Option<u64>,
+}
+
+impl CorsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(self.allow_origins.is_some())
+    }
+}
+
+/// Deserializes the `cors` key, either a boolean or a `[cors]` table.
+fn deserialize_cors<'de, D>(deserializer: D) -> std::result::Result<Option<CorsConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct CorsVisitor;
+
+    impl<'de> Visitor<'de> for CorsVisitor {
+        type Value = Option<CorsConfig>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a boolean or a table")
+        }
+
+        fn visit_bool<E>(self, enabled: bool) -> std::result::Result<Self::Value, E> {
+            Ok(Some(CorsConfig {
+                enabled: Some(enabled),
+                ..CorsConfig::default()
+            }))
+        }
+
+        fn visit_map<A>(self, map: A) -> std::result::Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            CorsConfig::deserialize(MapAccessDeserializer::new(map)).map(Some)
+        }
+    }
+
+    deserializer.deserialize_any(CorsVisitor)
+}
+
+/// Access log settings, an entry is logged for every request once its
+/// response has been sent.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AccessLogConfig {
+    /// Log requests. Enabled by default when `path` is provided.
+    pub enabled: Option<bool>,
+    /// Format of log entries.
+    pub format: Option<AccessLogFormat>,
+    /// File entries are appended to, standard output when not provided.
+    pub path: Option<PathBuf>,
+}
+
+impl AccessLogConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(self.path.is_some())
+    }
+
+    pub fn format(&self) -> AccessLogFormat {
+        self.format.unwrap_or(AccessLogFormat::Combined)
+    }
+}
+
+/// Format of access log entries.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Common Log Format
+    Common,
+    /// Combined Log Format, the Common Log Format followed by the `Referer`
+    /// and `User-Agent` headers
+    Combined,
+    /// JSON object per line
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "common" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(format!("Invalid access log format: {}", s)),
+        }
+    }
+}
+
+/// Format of log events.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, one event per line with its fields
+    #[default]
+    Text,
+    /// JSON object per line
+    Json,
+    /// Human readable, shorter lines
+    Compact,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            "compact" => Ok(LogFormat::Compact),
+            _ => Err(format!("Invalid log format: {}", s)),
+        }
+    }
+}
+
+/// `Cache-Control` policy of file responses. Files not matching any rule
+/// are served with `max-age=2500`.
+#[derive(Clone, Debug, Default)]
+pub struct CacheControl {
+    /// Serve every file with `no-cache`, regardless of `rules`.
+    pub no_cache: bool,
+    /// Rules evaluated in order, the first rule matching a file applies.
+    pub rules: Vec<CacheControlRule>,
+}
+
+/// `Cache-Control` directives for files matching a glob pattern, read from
+/// `[[cache-control]]` tables.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CacheControlRule {
+    /// Pattern matched against the file path relative to the root directory,
+    /// such as `*.html` or `assets/**`
+    pub pattern: Glob,
+    /// Directives sent for matching files, such as `["no-cache"]`
+    pub directives: Vec<CacheControlDirective>,
+}
+
+/// Content coding used to compress responses.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    Gzip,
+    Br,
+    Zstd,
+}
+
+impl FromStr for ContentEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(ContentEncoding::Gzip),
+            "br" => Ok(ContentEncoding::Br),
+            "zstd" => Ok(ContentEncoding::Zstd),
+            _ => Err(format!("Invalid encoding: {}", s)),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// Path to the PEM encoded certificate chain.
+    pub cert: PathBuf,
+    /// Path to the PEM encoded private key.
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BasicAuth {
     pub username: String,
     pub password: String,
@@ -52,17 +745,624 @@ impl FromStr for BasicAuth {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let parts = s.split(":").collect::<Vec<&str>>();
-
-        if parts.len() != 2 {
+        let Some((username, password)) = s.split_once(':') else {
             bail!(
                 "Expected a string with a colon to separe username and password for Basic Authentication."
             );
-        }
+        };
 
         Ok(BasicAuth {
-            username: parts[0].into(),
-            password: parts[1].into(),
+            username: username.into(),
+            password: password.into(),
+        })
+    }
+}
+
+/// Service selectable from the configuration file through the `service` key.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceKind {
+    FileServer,
+    FileExplorer,
+    Proxy,
+}
+
+impl FromStr for ServiceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "file-server" => Ok(ServiceKind::FileServer),
+            "file-explorer" => Ok(ServiceKind::FileExplorer),
+            "proxy" => Ok(ServiceKind::Proxy),
+            _ => Err(format!("Invalid service: {}", s)),
+        }
+    }
+}
+
+/// Settings specific to a single service, read from the `[file-server]`,
+/// `[file-explorer]` and `[proxy]` tables.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ServiceSection {
+    /// Directory to serve files from
+    pub path: Option<PathBuf>,
+    /// Origin server to forward requests to
+    pub upstream: Option<Upstream>,
+    /// Credentials required to access the service
+    pub basic_auth: Option<BasicAuth>,
+}
+
+/// A service mounted under a path prefix, read from `[[routes]]` tables.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RouteSection {
+    /// Path prefix the service is mounted at, `/` for the file explorer
+    pub prefix: String,
+    /// Service handling requests under `prefix`
+    pub service: ServiceKind,
+    /// Directory to serve files from
+    pub path: Option<PathBuf>,
+    /// Origin server to forward requests to
+    pub upstream: Option<Upstream>,
+    /// Credentials required to access the service
+    pub basic_auth: Option<BasicAuth>,
+    /// Client addresses allowed to access the route
+    #[serde(default)]
+    pub allow: Vec<IpNetwork>,
+    /// Client addresses rejected from the route
+    #[serde(default)]
+    pub deny: Vec<IpNetwork>,
+    /// Request methods `allow` and `deny` apply to, such as `["POST"]` to only
+    /// restrict uploads to the file explorer, every method when empty
+    #[serde(default)]
+    pub filter_methods: Vec<String>,
+    /// Limits on the requests and transfers of each client to the route
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Maximum size of uploaded files in bytes
+    pub max_upload_size: Option<u64>,
+}
+
+impl TryFrom<RouteSection> for Route {
+    type Error = Error;
+
+    fn try_from(val: RouteSection) -> Result<Self> {
+        Ok(Route {
+            service: Service::new(val.service, val.path, val.upstream, val.basic_auth)
+                .with_context(|| format!("Invalid route \"{}\".", val.prefix))?,
+            prefix: val.prefix,
+            ip_filter: IpFilter {
+                allow: val.allow,
+                deny: val.deny,
+                methods: val
+                    .filter_methods
+                    .iter()
+                    .map(|method| {
+                        Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                            .with_context(|| format!("Invalid filter method \"{method}\"."))
+                    })
+                    .collect::<Result<_>>()?,
+            },
+            rate_limit: val.rate_limit,
+            max_upload_size: val.max_upload_size,
+        })
+    }
+}
+
+/// A service selected by host name, read from `[[virtual-hosts]]` tables.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct VirtualHostSection {
+    /// Host names served by this virtual host
+    pub hosts: Vec<HostPattern>,
+    /// Service handling requests for `hosts`
+    pub service: ServiceKind,
+    /// Directory to serve files from
+    pub path: Option<PathBuf>,
+    /// Origin server to forward requests to
+    pub upstream: Option<Upstream>,
+    /// Credentials required to access the service
+    pub basic_auth: Option<BasicAuth>,
+    /// Headers added to every response
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Path to the PEM encoded certificate chain for `hosts`
+    pub tls_cert: Option<PathBuf>,
+    /// Path to the PEM encoded private key for `hosts`
+    pub tls_key: Option<PathBuf>,
+}
+
+impl TryFrom<VirtualHostSection> for VirtualHost {
+    type Error = Error;
+
+    fn try_from(val: VirtualHostSection) -> Result<Self> {
+        if val.hosts.is_empty() {
+            bail!("Virtual hosts must define at least one host name.");
+        }
+
+        let headers = header_map(val.headers)?;
+
+        let tls = match (val.tls_cert, val.tls_key) {
+            (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+            (None, None) => None,
+            _ => bail!("Virtual hosts must define both \"tls-cert\" and \"tls-key\"."),
+        };
+
+        Ok(VirtualHost {
+            hosts: val.hosts,
+            service: Service::new(val.service, val.path, val.upstream, val.basic_auth)?,
+            headers,
+            tls,
+        })
+    }
+}
+
+/// Rule modifying response headers, read from `[[headers]]` tables.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HeaderRuleSection {
+    /// Pattern matched against the request path
+    pub path: Option<Glob>,
+    /// Status codes of matching responses
+    pub status: Vec<u16>,
+    /// MIME types of matching responses
+    pub mime: Vec<String>,
+    /// Headers replacing any header with the same name
+    pub set: BTreeMap<String, String>,
+    /// Headers added along with any header with the same name
+    pub append: BTreeMap<String, String>,
+    /// Headers removed from responses
+    pub remove: Vec<String>,
+}
+
+impl TryFrom<HeaderRuleSection> for HeaderRule {
+    type Error = Error;
+
+    fn try_from(val: HeaderRuleSection) -> Result<Self> {
+        let status = val
+            .status
+            .into_iter()
+            .map(|status| {
+                StatusCode::from_u16(status)
+                    .with_context(|| format!("Invalid status code \"{status}\"."))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let remove = val
+            .remove
+            .iter()
+            .map(|name| {
+                HeaderName::from_str(name)
+                    .with_context(|| format!("Invalid header name \"{name}\"."))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(HeaderRule {
+            path: val.path.map(|glob| glob.compile_matcher()),
+            status,
+            mime: val.mime,
+            set: header_map(val.set)?,
+            append: header_map(val.append)?,
+            remove,
         })
     }
 }
+
+/// Builds a `HeaderMap` from header names and values read from the
+/// configuration file.
+fn header_map(headers: BTreeMap<String, String>) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+
+    for (name, value) in headers {
+        let name = HeaderName::from_str(&name)
+            .with_context(|| format!("Invalid header name \"{name}\"."))?;
+        let value = HeaderValue::from_str(&value)
+            .with_context(|| format!("Invalid value for header \"{name}\"."))?;
+
+        map.insert(name, value);
+    }
+
+    Ok(map)
+}
+
+/// Representation of the TOML configuration file.
+///
+/// Every field is optional so values can be layered below environment
+/// variables and CLI flags, which always take precedence over the file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigFile {
+    pub host: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub listen: Option<Listen>,
+    pub socket_mode: Option<SocketMode>,
+    #[serde(deserialize_with = "deserialize_cors")]
+    pub cors: Option<CorsConfig>,
+    pub service: Option<ServiceKind>,
+    pub tls: Option<bool>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub http2: Option<Http2Config>,
+    pub connection: Option<ConnectionConfig>,
+    pub compression: Option<CompressionConfig>,
+    pub no_cache: Option<bool>,
+    pub cache_control: Option<Vec<CacheControlRule>>,
+    pub access_log: Option<AccessLogConfig>,
+    pub log_level: Option<String>,
+    pub log_format: Option<LogFormat>,
+    /// Seconds to wait for active connections to complete on shutdown
+    pub shutdown_timeout: Option<u64>,
+    pub file_server: Option<ServiceSection>,
+    pub file_explorer: Option<ServiceSection>,
+    pub proxy: Option<ServiceSection>,
+    pub routes: Option<Vec<RouteSection>>,
+    pub virtual_hosts: Option<Vec<VirtualHostSection>>,
+    pub headers: Option<Vec<HeaderRuleSection>>,
+    pub secure_headers: Option<bool>,
+    pub allow: Option<Vec<IpNetwork>>,
+    pub deny: Option<Vec<IpNetwork>>,
+    pub deny_action: Option<DenyAction>,
+    pub trusted_proxies: Option<Vec<IpNetwork>>,
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Maximum size of uploaded files in bytes
+    pub max_upload_size: Option<u64>,
+}
+
+impl ConfigFile {
+    /// Reads and deserializes the configuration file at `path`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+
+        Self::from_str(&content).with_context(|| format!("Invalid config file: {}", path.display()))
+    }
+
+    /// Loads the configuration file from the provided `path`, otherwise looks
+    /// for a `config.toml` in the current working directory.
+    ///
+    /// If no path is provided and no `config.toml` is found, an empty
+    /// `ConfigFile` is returned so defaults apply. A `config.toml` which
+    /// cannot be loaded is an error, as for an explicit `path`, rather than
+    /// silently falling back to defaults.
+    pub fn discover(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::from_path(path),
+            None => Self::from_discovered_path(Path::new(DEFAULT_CONFIG_FILE)),
+        }
+    }
+
+    fn from_discovered_path(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        Self::from_path(path)
+    }
+
+    /// Retrieves the `[file-server]`, `[file-explorer]` or `[proxy]` table for
+    /// the provided `ServiceKind`.
+    pub fn section(&self, kind: ServiceKind) -> Option<&ServiceSection> {
+        match kind {
+            ServiceKind::FileServer => self.file_server.as_ref(),
+            ServiceKind::FileExplorer => self.file_explorer.as_ref(),
+            ServiceKind::Proxy => self.proxy.as_ref(),
+        }
+    }
+}
+
+/// Ensures the provided `path` is an existing directory which contents can
+/// be listed by the current user.
+pub fn validate_root_directory(path: &Path) -> Result<()> {
+    if !path.is_dir() {
+        bail!(
+            "Root directory {} does not exist or is not a directory.",
+            path.display()
+        );
+    }
+
+    read_dir(path)
+        .with_context(|| format!("Root directory {} is not readable.", path.display()))?;
+
+    Ok(())
+}
+
+impl FromStr for ConfigFile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        toml::from_str(s).map_err(Error::msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use http::Method;
+    use tempfile::TempDir;
+
+    use super::{
+        ConfigFile, ConnectionConfig, DEFAULT_HEADER_READ_TIMEOUT, DEFAULT_IDLE_TIMEOUT,
+        HeaderRule, HeaderRuleSection, Http2Config, Listen, MAX_WINDOW_SIZE, Route, Service,
+        ServiceKind, SocketMode, VirtualHost,
+    };
+
+    #[test]
+    fn parses_config_file() {
+        let config = ConfigFile::from_str(
+            r#"
+            host = "127.0.0.1"
+            port = 8080
+            cors = true
+            service = "file-server"
+
+            [file-server]
+            path = "./dist"
+
+            [file-server.basic-auth]
+            username = "john"
+            password = "appleseed"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.host, Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert_eq!(config.port, Some(8080));
+        assert!(config.cors.as_ref().unwrap().is_enabled());
+        assert_eq!(config.service, Some(ServiceKind::FileServer));
+        assert_eq!(
+            config.section(ServiceKind::FileServer).unwrap().path,
+            Some(PathBuf::from("./dist"))
+        );
+        assert_eq!(
+            config
+                .section(ServiceKind::FileServer)
+                .unwrap()
+                .basic_auth
+                .as_ref()
+                .unwrap()
+                .username,
+            "john"
+        );
+        assert!(config.section(ServiceKind::FileExplorer).is_none());
+    }
+
+    #[test]
+    fn parses_routes() {
+        let config = ConfigFile::from_str(
+            r#"
+            [[routes]]
+            prefix = "/"
+            service = "file-explorer"
+            allow = ["10.0.0.0/8"]
+            filter-methods = ["post"]
+
+            [[routes]]
+            prefix = "/static"
+            service = "file-server"
+            path = "./public"
+            max-upload-size = 1024
+
+            [routes.rate-limit]
+            requests-per-second = 10
+            "#,
+        )
+        .unwrap();
+        let mut routes = config.routes.unwrap();
+
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[1].prefix, "/static");
+        assert_eq!(routes[1].service, ServiceKind::FileServer);
+        assert_eq!(routes[1].path, Some(PathBuf::from("./public")));
+
+        let route = Route::try_from(routes.remove(1)).unwrap();
+
+        assert_eq!(route.max_upload_size, Some(1024));
+        assert!(route.ip_filter.applies_to(&Method::GET));
+        assert_eq!(
+            route.rate_limit.requests_per_second.map(|rate| rate.get()),
+            Some(10)
+        );
+
+        let route = Route::try_from(routes.remove(0)).unwrap();
+
+        assert!(route.ip_filter.applies_to(&Method::POST));
+        assert!(!route.ip_filter.applies_to(&Method::GET));
+    }
+
+    #[test]
+    fn parses_route_from_cli() {
+        let route = Route::from_str("/static=file-server:./public").unwrap();
+
+        assert_eq!(route.prefix, "/static");
+        assert!(matches!(route.service, Service::FileServer { .. }));
+        assert_eq!(route.service.root_directory(), Some(Path::new("./public")));
+        assert!(Route::from_str("/static").is_err());
+        assert!(Route::from_str("/static=proxy").is_err());
+
+        let route = Route::from_str("/api=proxy:http://127.0.0.1:3000").unwrap();
+
+        assert!(matches!(route.service, Service::Proxy { .. }));
+        assert!(Route::from_str("/api=proxy:https://127.0.0.1:3000").is_err());
+        assert!(Route::from_str("/api=proxy:127.0.0.1:3000").is_err());
+    }
+
+    #[test]
+    fn mounts_file_explorer_at_root_only() {
+        assert!(
+            Route::from_str("/=file-explorer:./")
+                .unwrap()
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            Route::from_str("/files=file-explorer:./")
+                .unwrap()
+                .validate()
+                .is_err()
+        );
+        assert!(
+            Route::from_str("/files=file-server:./")
+                .unwrap()
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn parses_virtual_hosts() {
+        let config = ConfigFile::from_str(
+            r#"
+            [[virtual-hosts]]
+            hosts = ["Docs.Example.com", "*.docs.example.com"]
+            service = "file-server"
+            path = "./docs"
+
+            [virtual-hosts.headers]
+            x-frame-options = "DENY"
+            "#,
+        )
+        .unwrap();
+        let section = config.virtual_hosts.unwrap().remove(0);
+        let virtual_host = VirtualHost::try_from(section).unwrap();
+
+        assert_eq!(virtual_host.hosts.len(), 2);
+        assert_eq!(
+            virtual_host.service.root_directory(),
+            Some(Path::new("./docs"))
+        );
+        assert_eq!(virtual_host.headers["x-frame-options"], "DENY");
+        assert!(virtual_host.tls.is_none());
+        assert!(ConfigFile::from_str("[[virtual-hosts]]\nhosts = [\"a/b\"]").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_header_rules() {
+        let rule = |section: HeaderRuleSection| HeaderRule::try_from(section);
+
+        assert!(
+            rule(HeaderRuleSection {
+                status: vec![42],
+                ..HeaderRuleSection::default()
+            })
+            .is_err()
+        );
+        assert!(
+            rule(HeaderRuleSection {
+                remove: vec![String::from("x header")],
+                ..HeaderRuleSection::default()
+            })
+            .is_err()
+        );
+        assert!(
+            rule(HeaderRuleSection {
+                set: [(String::from("x-frame-options"), String::from("DENY\n"))].into(),
+                ..HeaderRuleSection::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn parses_listen_addresses() {
+        assert_eq!(
+            Listen::from_str("127.0.0.1:8080").unwrap(),
+            Listen::Tcp(SocketAddr::from(([127, 0, 0, 1], 8080)))
+        );
+        assert_eq!(
+            Listen::from_str("unix:/run/http-server.sock").unwrap(),
+            Listen::Unix(PathBuf::from("/run/http-server.sock"))
+        );
+        assert!(Listen::from_str("unix:").is_err());
+        assert!(Listen::from_str("localhost").is_err());
+        assert_eq!(SocketMode::from_str("660").unwrap(), SocketMode(0o660));
+        assert_eq!(SocketMode::from_str("0600").unwrap(), SocketMode(0o600));
+        assert!(SocketMode::from_str("680").is_err());
+        assert!(SocketMode::from_str("7777").is_err());
+    }
+
+    #[test]
+    fn resolves_connection_timeouts() {
+        let connection = ConnectionConfig {
+            header_read_timeout: Some(0),
+            request_timeout: Some(60),
+            ..ConnectionConfig::default()
+        };
+
+        assert_eq!(connection.header_read_timeout(), None);
+        assert_eq!(connection.idle_timeout(), Some(DEFAULT_IDLE_TIMEOUT));
+        assert_eq!(connection.request_timeout(), Some(Duration::from_secs(60)));
+        assert_eq!(
+            ConnectionConfig::default().header_read_timeout(),
+            Some(DEFAULT_HEADER_READ_TIMEOUT)
+        );
+        assert_eq!(ConnectionConfig::default().request_timeout(), None);
+    }
+
+    #[test]
+    fn validates_window_sizes() {
+        let config = ConfigFile::from_str(
+            r#"
+            [http2]
+            initial-stream-window-size = 2147483647
+            initial-connection-window-size = 4294967295
+            "#,
+        )
+        .unwrap();
+        let mut http2 = config.http2.unwrap();
+
+        assert!(http2.validate().is_err());
+
+        http2.initial_connection_window_size = Some(MAX_WINDOW_SIZE);
+
+        assert!(http2.validate().is_ok());
+        assert!(Http2Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for toml in [
+            "[compression]\nencodings = [\"deflate\"]",
+            "[[cache-control]]\npattern = \"*\"\ndirectives = [\"max-age\"]",
+            "[[cache-control]]\npattern = \"[\"\ndirectives = [\"no-cache\"]",
+            "[access-log]\nformat = \"apache\"",
+            "cors = \"yes\"",
+            "[cors]\nallow-origin = [\"*\"]",
+            "[cors]\nallow-origins = [\"example.com\"]",
+            "[rate-limit]\nrequests-per-second = 0",
+            "[connection]\nmax-connections = 0",
+        ] {
+            assert!(ConfigFile::from_str(toml).is_err(), "{toml}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_discovered_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+
+        write(&path, "prot = 7878").unwrap();
+
+        let err = ConfigFile::from_discovered_path(&path).unwrap_err();
+
+        assert!(format!("{err:#}").contains("prot"), "{err:#}");
+        assert!(ConfigFile::discover(Some(&path)).is_err());
+
+        let file = ConfigFile::from_discovered_path(&dir.path().join("missing.toml")).unwrap();
+
+        assert!(file.port.is_none());
+    }
+
+    #[test]
+    fn reports_offending_key() {
+        let err = ConfigFile::from_str("port = \"7878\"").unwrap_err();
+        assert!(err.to_string().contains("port"), "{err}");
+
+        let err = ConfigFile::from_str("prot = 7878").unwrap_err();
+        assert!(err.to_string().contains("prot"), "{err}");
+    }
+}
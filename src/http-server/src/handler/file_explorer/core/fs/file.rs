@@ -1,9 +1,9 @@
 use std::fs::Metadata;
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
 use mime_guess::{Mime, from_path};
-use tokio::io::AsyncReadExt;
 
 /// Wrapper around `tokio::fs::File` built from a OS ScopedFileSystem file
 /// providing `std::fs::Metadata` and the path to such file
@@ -31,9 +31,13 @@ impl File {
         self.metadata.len()
     }
 
-    pub async fn bytes(&mut self) -> Result<Vec<u8>> {
-        let mut buf = Vec::with_capacity(self.size() as usize);
-        self.file.read_to_end(&mut buf).await?;
-        Ok(buf)
+    pub fn last_modified(&self) -> Result<DateTime<Local>> {
+        let modified = self
+            .metadata
+            .modified()
+            .context("Failed to read last modified time for file")?;
+        let modified: DateTime<Local> = modified.into();
+
+        Ok(modified)
     }
 }
@@ -3,26 +3,34 @@ mod proto;
 mod utils;
 
 use core::Entry;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs::read_dir;
 use std::path::{Component, Path, PathBuf};
+use std::pin::pin;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::StreamExt;
 use http::HeaderName;
-use http::{HeaderValue, Method, Response, StatusCode, Uri, header::CONTENT_TYPE, request::Parts};
-use http_body_util::{BodyExt, Full};
-use hyper::body::Incoming;
+use http::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use http::{HeaderMap, HeaderValue, Method, Response, StatusCode, Uri, request::Parts};
+use http_body_util::BodyExt;
+use hyper::body::Body;
 use percent_encoding::{percent_decode_str, utf8_percent_encode};
 use proto::{DirectoryEntry, DirectoryIndex, EntryType, Sort};
 use rust_embed::Embed;
-use tokio::fs::File;
+use tokio::fs::{OpenOptions, remove_file, rename};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
+use tracing::{debug, error, warn};
 
 use crate::handler::Handler;
-use crate::server::{HttpRequest, HttpResponse};
+use crate::handler::range::{RangeRequest, Validators, make_range_response};
+use crate::server::{HttpRequest, HttpResponse, full_body};
 
 use self::proto::BreadcrumbItem;
 use self::utils::{PERCENT_ENCODE_SET, decode_uri, encode_uri};
@@ -38,22 +46,45 @@ struct FileExplorerAssets;
 pub enum UploadFileMessage {
     Progress(u64),
     Failed(String),
+    /// The upload exceeded the maximum upload size and was discarded
+    TooLarge,
 }
 
+/// Error for uploads exceeding the maximum upload size.
+#[derive(Debug)]
+struct UploadTooLarge;
+
+impl fmt::Display for UploadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Upload exceeds the maximum upload size")
+    }
+}
+
+impl std::error::Error for UploadTooLarge {}
+
 pub struct FileExplorer {
     file_explorer: core::FileExplorer,
+    /// Root directory served, uploaded files are written to it rather than
+    /// to the current working directory
     path: PathBuf,
+    /// Maximum size of uploaded files in bytes, unlimited when `None`
+    max_upload_size: Option<u64>,
 }
 
 impl FileExplorer {
-    pub fn new(path: PathBuf) -> Self {
+    pub fn new(path: PathBuf, max_upload_size: Option<u64>) -> Self {
         Self {
             file_explorer: core::FileExplorer::new(path.clone()),
             path,
+            max_upload_size,
         }
     }
 
-    async fn handle_api(&self, parts: Parts, body: Incoming) -> Result<HttpResponse> {
+    async fn handle_api<B>(&self, parts: Parts, body: B) -> Result<HttpResponse>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: fmt::Display + Send,
+    {
         let path = Self::parse_req_uri(parts.uri.clone())?;
 
         match parts.method {
@@ -63,7 +94,7 @@ impl FileExplorer {
                         let directory_index =
                             self.marshall_directory_index(dir.path()).await.unwrap();
                         let json = serde_json::to_string(&directory_index).unwrap();
-                        let body = Full::new(Bytes::from(json));
+                        let body = full_body(json);
                         let mut response = Response::new(body);
                         let mut headers = response.headers().clone();
 
@@ -72,54 +103,99 @@ impl FileExplorer {
 
                         Ok(response)
                     }
-                    Entry::File(mut file) => {
-                        let body = Full::new(Bytes::from(file.bytes().await.unwrap()));
-                        let mut response = Response::new(body);
-                        let mut headers = response.headers().clone();
-
-                        headers.append(CONTENT_TYPE, file.mime().to_string().try_into().unwrap());
-                        *response.headers_mut() = headers;
-
-                        Ok(response)
+                    Entry::File(file) => {
+                        let size = file.size();
+                        let content_type = file.mime().to_string();
+                        let last_modified = file.last_modified()?;
+                        let etag = Validators::etag(size, &last_modified);
+                        let last_modified = Validators::last_modified(&last_modified);
+                        // Validators let clients resume downloads with `If-Range`
+                        let range = RangeRequest::evaluate(
+                            &parts.headers,
+                            size,
+                            Validators {
+                                etag: Some(&etag),
+                                last_modified: Some(&last_modified),
+                            },
+                        );
+                        let builder = Response::builder()
+                            .header(ACCEPT_RANGES, "bytes")
+                            .header(CONTENT_TYPE, &content_type)
+                            .header(ETAG, &etag)
+                            .header(LAST_MODIFIED, &last_modified);
+
+                        make_range_response(builder, file.file, size, &content_type, range).await
                     }
                 },
                 Err(err) => {
                     let message = format!("Failed to resolve path: {err}");
-                    Ok(Response::new(Full::new(Bytes::from(message))))
+                    Ok(Response::new(full_body(message)))
                 }
             },
             Method::POST => self.handle_file_upload(parts, body).await,
-            _ => Ok(Response::new(Full::new(Bytes::from("Unsupported method")))),
+            _ => Ok(Response::new(full_body("Unsupported method"))),
         }
     }
 
-    async fn handle_file_upload(&self, parts: Parts, body: Incoming) -> Result<HttpResponse> {
+    async fn handle_file_upload<B>(&self, parts: Parts, body: B) -> Result<HttpResponse>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: fmt::Display + Send,
+    {
+        // Uploads declaring a size above the limit are rejected before reading
+        // the body, so clients sending `Expect: 100-continue` never send it
+        if let Some(max) = self.max_upload_size
+            && content_length(&parts.headers).is_some_and(|len| len > max)
+        {
+            return Ok(payload_too_large());
+        }
+
         if let Err(err) = self.process_multipart(body, parts).await {
+            if err.is::<UploadTooLarge>() {
+                return Ok(payload_too_large());
+            }
+
             return Ok(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Full::from(format!("INTERNAL SERVER ERROR: {err}")))
+                .body(full_body(format!("INTERNAL SERVER ERROR: {err}")))
                 .unwrap());
         }
 
-        Ok(Response::new(Full::from("Success")))
+        Ok(Response::new(full_body("Success")))
     }
 
-    async fn process_multipart(&self, bytes: Incoming, parts: Parts) -> Result<()> {
+    async fn process_multipart<B>(&self, bytes: B, parts: Parts) -> Result<()>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: fmt::Display + Send,
+    {
         let file_name = parts
             .headers
             .get(X_FILE_NAME_HTTP_HEADER)
             .and_then(|hv| hv.to_str().ok())
-            .context(format!("Missing '{X_FILE_NAME}' header"))?
-            .to_string();
+            .context(format!("Missing '{X_FILE_NAME}' header"))?;
+        let file_name = Path::new(file_name)
+            .file_name()
+            .context(format!("Invalid '{X_FILE_NAME}' header"))?;
+        let file_path = self.path.join(file_name);
+        let temp_path = self.path.join(temp_file_name(file_name));
+        let max_upload_size = self.max_upload_size;
         let (tx, mut rx) = mpsc::channel(100);
 
         tokio::spawn(async move {
-            let mut stream = bytes.into_data_stream();
-            let mut file = match File::create(file_name).await {
+            let mut stream = pin!(bytes.into_data_stream());
+            // The upload is written to a temporary file first, so an existing
+            // file is only replaced once the upload completes
+            let mut file = match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp_path)
+                .await
+            {
                 Ok(f) => f,
                 Err(err) => {
                     if let Err(err) = tx.send(UploadFileMessage::Failed(err.to_string())).await {
-                        eprintln!("Failed to send message through mpsc channel. {err:?}");
+                        error!(?err, "Failed to send message through mpsc channel");
                     }
 
                     return;
@@ -127,26 +203,74 @@ impl FileExplorer {
             };
 
             let mut total = 0u64;
-
-            while let Some(Ok(bytes)) = stream.next().await {
-                total += bytes.len() as u64;
-
-                if let Err(err) = file.write_all(&bytes).await {
-                    if let Err(err) = tx.send(UploadFileMessage::Failed(err.to_string())).await {
-                        eprintln!("Failed to send message through mpsc channel. {err:?}");
+            let mut failure = None;
+
+            while let Some(chunk) = stream.next().await {
+                failure = match chunk {
+                    Ok(bytes) => {
+                        total += bytes.len() as u64;
+
+                        if max_upload_size.is_some_and(|max| total > max) {
+                            Some(UploadFileMessage::TooLarge)
+                        } else if let Err(err) = file.write_all(&bytes).await {
+                            Some(UploadFileMessage::Failed(err.to_string()))
+                        } else {
+                            None
+                        }
                     }
+                    Err(err) => Some(UploadFileMessage::Failed(err.to_string())),
+                };
 
+                if failure.is_some() {
                     break;
                 }
 
-                if let Err(err) = tx.send(UploadFileMessage::Progress(total)).await {
-                    eprintln!("Failed to send message through mpsc channel. {err:?}");
+                // The request is gone, as when the client disconnects, so
+                // nothing awaits the rest of the upload
+                if tx.send(UploadFileMessage::Progress(total)).await.is_err() {
+                    warn!(path = %file_path.display(), "Upload interrupted");
+                    failure = Some(UploadFileMessage::Failed(String::from(
+                        "Upload interrupted",
+                    )));
+                    break;
+                }
+            }
+
+            if failure.is_none()
+                && let Err(err) = async {
+                    file.flush().await?;
+                    rename(&temp_path, &file_path).await
+                }
+                .await
+            {
+                failure = Some(UploadFileMessage::Failed(err.to_string()));
+            }
+
+            if let Some(message) = failure {
+                // Partial files are removed rather than left behind as if the
+                // upload had completed
+                drop(file);
+
+                if let Err(err) = remove_file(&temp_path).await {
+                    error!(?err, "Failed to remove partially uploaded file");
+                }
+
+                if !tx.is_closed()
+                    && let Err(err) = tx.send(message).await
+                {
+                    error!(?err, "Failed to send message through mpsc channel");
                 }
             }
         });
 
         while let Some(message) = rx.recv().await {
-            println!("{message:?}");
+            debug!(?message, "File upload");
+
+            match message {
+                UploadFileMessage::Progress(_) => {}
+                UploadFileMessage::Failed(err) => bail!("Failed to upload file: {err}"),
+                UploadFileMessage::TooLarge => return Err(UploadTooLarge.into()),
+            }
         }
 
         Ok(())
@@ -314,7 +438,7 @@ impl Handler for FileExplorer {
         if let Some(file) = FileExplorerAssets::get(path) {
             let content_type = mime_guess::from_path(path).first_or_octet_stream();
             let content_type = HeaderValue::from_str(content_type.as_ref()).unwrap();
-            let body = Full::new(Bytes::from(file.data.to_vec()));
+            let body = full_body(file.data.into_owned());
             let mut response = Response::new(body);
             let mut headers = response.headers().clone();
 
@@ -325,7 +449,7 @@ impl Handler for FileExplorer {
         }
 
         let index = FileExplorerAssets::get("index.html").unwrap();
-        let body = Full::new(Bytes::from(index.data.to_vec()));
+        let body = full_body(index.data.into_owned());
         let mut response = Response::new(body);
         let mut headers = response.headers().clone();
 
@@ -335,3 +459,236 @@ impl Handler for FileExplorer {
         Ok(response)
     }
 }
+
+/// Name of the temporary file an upload of `file_name` is written to, unique
+/// to each upload in progress.
+fn temp_file_name(file_name: &OsStr) -> OsString {
+    static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+    let mut name = OsString::from(".");
+
+    name.push(file_name);
+    name.push(format!(
+        ".{}-{}.upload",
+        process::id(),
+        UPLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    name
+}
+
+/// Reads the size of the request body declared in `Content-Length`.
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+fn payload_too_large() -> HttpResponse {
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(full_body("Payload Too Large"))
+        .expect("Failed to build Payload Too Large response")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::fs::{read_dir, read_to_string, write};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures::stream;
+    use http::{Request, StatusCode};
+    use http_body_util::{BodyExt, Empty, Full, StreamBody};
+    use hyper::body::Frame;
+    use tempfile::TempDir;
+    use tokio::time::{sleep, timeout};
+
+    use super::FileExplorer;
+
+    fn upload_parts(file_name: &str, content_length: Option<u64>) -> http::request::Parts {
+        let mut builder = Request::post("/api/v1/").header("x-file-name", file_name);
+
+        if let Some(len) = content_length {
+            builder = builder.header("content-length", len);
+        }
+
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    fn chunked(
+        chunks: &[&'static str],
+    ) -> StreamBody<impl futures::Stream<Item = Result<Frame<Bytes>, Infallible>>> {
+        StreamBody::new(stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok(Frame::data(Bytes::from_static(chunk.as_bytes()))))
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    fn get_parts(path: &str, headers: &[(&str, &str)]) -> http::request::Parts {
+        let mut builder = Request::get(path);
+
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    fn file_names(dir: &TempDir) -> Vec<String> {
+        let mut names = read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn rejects_declared_oversized_uploads() {
+        let dir = TempDir::new().unwrap();
+        let explorer = FileExplorer::new(dir.path().to_path_buf(), Some(4));
+        let response = explorer
+            .handle_file_upload(upload_parts("big.txt", Some(5)), Empty::<Bytes>::new())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(file_names(&dir).is_empty());
+    }
+
+    #[tokio::test]
+    async fn aborts_oversized_uploads() {
+        let dir = TempDir::new().unwrap();
+        let explorer = FileExplorer::new(dir.path().to_path_buf(), Some(4));
+        let response = explorer
+            .handle_file_upload(upload_parts("big.txt", None), chunked(&["abc", "def"]))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(file_names(&dir).is_empty());
+    }
+
+    #[tokio::test]
+    async fn replaces_files_only_once_uploaded() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("keep.txt");
+        let explorer = FileExplorer::new(dir.path().to_path_buf(), Some(4));
+
+        write(&path, "keep").unwrap();
+
+        let response = explorer
+            .handle_file_upload(upload_parts("keep.txt", None), chunked(&["abc", "def"]))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(read_to_string(&path).unwrap(), "keep");
+        assert_eq!(file_names(&dir), ["keep.txt"]);
+
+        let response = explorer
+            .handle_file_upload(
+                upload_parts("keep.txt", None),
+                Full::new(Bytes::from("new")),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(read_to_string(&path).unwrap(), "new");
+        assert_eq!(file_names(&dir), ["keep.txt"]);
+    }
+
+    #[tokio::test]
+    async fn resumes_downloads() {
+        let dir = TempDir::new().unwrap();
+        let explorer = FileExplorer::new(dir.path().to_path_buf(), None);
+
+        write(dir.path().join("file.txt"), "0123456789").unwrap();
+
+        let response = explorer
+            .handle_api(get_parts("/api/v1/file.txt", &[]), Empty::<Bytes>::new())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key("etag"));
+
+        let last_modified = response.headers()["last-modified"].to_str().unwrap();
+        let headers = [("range", "bytes=4-"), ("if-range", last_modified)];
+        let response = explorer
+            .handle_api(
+                get_parts("/api/v1/file.txt", &headers),
+                Empty::<Bytes>::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["content-range"], "bytes 4-9/10");
+        assert_eq!(
+            response.into_body().collect().await.unwrap().to_bytes(),
+            "456789"
+        );
+
+        // A changed file is downloaded again from the start
+        let headers = [
+            ("range", "bytes=4-"),
+            ("if-range", "Thu, 01 Jan 1970 00:00:00 GMT"),
+        ];
+        let response = explorer
+            .handle_api(
+                get_parts("/api/v1/file.txt", &headers),
+                Empty::<Bytes>::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn discards_interrupted_uploads() {
+        let dir = TempDir::new().unwrap();
+        let explorer = Arc::new(FileExplorer::new(dir.path().to_path_buf(), None));
+        let (tx, rx) = futures::channel::mpsc::unbounded::<Result<Frame<Bytes>, Infallible>>();
+        let upload = tokio::spawn({
+            let explorer = Arc::clone(&explorer);
+
+            async move {
+                explorer
+                    .handle_file_upload(upload_parts("file.txt", None), StreamBody::new(rx))
+                    .await
+            }
+        });
+
+        tx.unbounded_send(Ok(Frame::data(Bytes::from("abc"))))
+            .unwrap();
+
+        while file_names(&dir).is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // Dropping the request, as hyper does when the client disconnects
+        upload.abort();
+        let _ = upload.await;
+        tx.unbounded_send(Ok(Frame::data(Bytes::from("def"))))
+            .unwrap();
+
+        let removed = async {
+            while !file_names(&dir).is_empty() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+
+        timeout(Duration::from_secs(5), removed).await.unwrap();
+        assert!(tx.is_closed());
+    }
+}
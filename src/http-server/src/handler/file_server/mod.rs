@@ -3,14 +3,12 @@ mod utils;
 
 use anyhow::Result;
 use async_trait::async_trait;
-use bytes::Bytes;
 use http::{Method, Response, StatusCode};
-use http_body_util::Full;
 
 use crate::handler::Handler;
-use crate::server::{HttpRequest, HttpResponse};
+use crate::server::{HttpRequest, HttpResponse, full_body};
 
-pub use crate::handler::file_server::service::FileServerConfig;
+pub use crate::handler::file_server::service::{CacheControlDirective, FileServerConfig};
 
 use self::service::FileServer as FileServerService;
 
@@ -32,16 +30,19 @@ impl Handler for FileServer {
         let (parts, _) = req.into_parts();
 
         if parts.uri.path().starts_with("/api/v1") {
-            let mut response = Response::new(Full::new(Bytes::from("Method Not Allowed")));
+            let mut response = Response::new(full_body("Method Not Allowed"));
             *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
             return Ok(response);
         }
 
         if parts.method == Method::GET {
-            return self.file_service.resolve(parts.uri.to_string()).await;
+            return self
+                .file_service
+                .resolve(parts.uri.to_string(), &parts.headers)
+                .await;
         }
 
-        let mut response = Response::new(Full::new(Bytes::from("Method Not Allowed")));
+        let mut response = Response::new(full_body("Method Not Allowed"));
         *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
         Ok(response)
     }
@@ -4,7 +4,6 @@ use std::path::PathBuf;
 use anyhow::{Context, Result};
 use chrono::{DateTime, Local};
 use mime_guess::{Mime, from_path};
-use tokio::io::AsyncReadExt;
 
 /// Wrapper around `tokio::fs::File` built from a OS ScopedFileSystem file
 /// providing `std::fs::Metadata` and the path to such file
@@ -41,10 +40,4 @@ impl File {
 
         Ok(modified)
     }
-
-    pub async fn bytes(&mut self) -> Result<Vec<u8>> {
-        let mut buf = Vec::with_capacity(self.size() as usize);
-        self.file.read_to_end(&mut buf).await?;
-        Ok(buf)
-    }
 }
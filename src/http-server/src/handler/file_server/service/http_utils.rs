@@ -1,19 +1,22 @@
 use std::fmt::Display;
+use std::str::FromStr;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Error, Result, bail};
+use bytes::Bytes;
 use chrono::{DateTime, Local, Utc};
 use http::response::Builder as HttpResponseBuilder;
-use http_body_util::Full;
-use hyper::body::Bytes;
+use http::{HeaderMap, StatusCode};
+use serde::Deserialize;
 
-use crate::server::HttpResponse;
+use crate::handler::range::{RangeRequest, Validators, make_range_response};
+use crate::server::{HttpResponse, full_body};
 
 use super::file::File;
+use super::preconditions::Precondition;
 
 /// HTTP Response `Cache-Control` directive
-///
-/// Allow dead code until we have support for cache control configuration
-#[allow(dead_code)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
 pub enum CacheControlDirective {
     /// Cache-Control: must-revalidate
     MustRevalidate,
@@ -33,6 +36,8 @@ pub enum CacheControlDirective {
     MaxAge(u64),
     /// Cache-Control: s-maxage=<seconds>
     SMaxAge(u64),
+    /// Cache-Control: immutable
+    Immutable,
 }
 
 impl Display for CacheControlDirective {
@@ -47,10 +52,49 @@ impl Display for CacheControlDirective {
             Self::ProxyRavalidate => write!(f, "proxy-revalidate"),
             Self::MaxAge(age) => write!(f, "max-age={}", age),
             Self::SMaxAge(age) => write!(f, "s-maxage={}", age),
+            Self::Immutable => write!(f, "immutable"),
         }
     }
 }
 
+impl FromStr for CacheControlDirective {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let directive = s.trim().to_ascii_lowercase();
+        let seconds = |value: &str| {
+            value
+                .parse::<u64>()
+                .with_context(|| format!("Invalid Cache-Control directive \"{s}\"."))
+        };
+
+        Ok(match directive.split_once('=') {
+            Some(("max-age", value)) => Self::MaxAge(seconds(value)?),
+            Some(("s-maxage", value)) => Self::SMaxAge(seconds(value)?),
+            Some(_) => bail!("Invalid Cache-Control directive \"{s}\"."),
+            None => match directive.as_str() {
+                "must-revalidate" => Self::MustRevalidate,
+                "no-cache" => Self::NoCache,
+                "no-store" => Self::NoStore,
+                "no-transform" => Self::NoTransform,
+                "public" => Self::Public,
+                "private" => Self::Private,
+                "proxy-revalidate" => Self::ProxyRavalidate,
+                "immutable" => Self::Immutable,
+                _ => bail!("Invalid Cache-Control directive \"{s}\"."),
+            },
+        })
+    }
+}
+
+impl TryFrom<String> for CacheControlDirective {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::from_str(&value)
+    }
+}
+
 #[derive(Debug)]
 pub struct ResponseHeaders {
     cache_control: String,
@@ -62,56 +106,84 @@ pub struct ResponseHeaders {
 impl ResponseHeaders {
     pub fn new(
         file: &File,
-        cache_control_directive: CacheControlDirective,
+        cache_control_directives: &[CacheControlDirective],
     ) -> Result<ResponseHeaders> {
         let last_modified = file.last_modified()?;
 
         Ok(ResponseHeaders {
-            cache_control: cache_control_directive.to_string(),
+            cache_control: ResponseHeaders::cache_control(cache_control_directives),
             content_type: ResponseHeaders::content_type(file),
             etag: ResponseHeaders::etag(file, &last_modified),
             last_modified: ResponseHeaders::last_modified(&last_modified),
         })
     }
 
+    fn cache_control(directives: &[CacheControlDirective]) -> String {
+        directives
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     fn content_type(file: &File) -> String {
         file.mime().to_string()
     }
 
     fn etag(file: &File, last_modified: &DateTime<Local>) -> String {
-        format!(
-            "W/\"{0:x}-{1:x}.{2:x}\"",
-            file.size(),
-            last_modified.timestamp(),
-            last_modified.timestamp_subsec_nanos(),
-        )
+        Validators::etag(file.size(), last_modified)
     }
 
     fn last_modified(last_modified: &DateTime<Local>) -> String {
-        format!(
-            "{} GMT",
-            last_modified
-                .with_timezone(&Utc)
-                .format("%a, %e %b %Y %H:%M:%S")
-        )
+        Validators::last_modified(last_modified)
     }
 }
 
+/// Builds the HTTP Response for `file`, serving only the ranges requested
+/// through the `Range` header of the request when present.
+///
+/// Request preconditions are evaluated first, responding with
+/// `304 Not Modified` or `412 Precondition Failed` without reading the file.
 pub async fn make_http_file_response(
-    mut file: File,
-    cache_control_directive: CacheControlDirective,
+    file: File,
+    cache_control_directives: &[CacheControlDirective],
+    req_headers: &HeaderMap,
 ) -> Result<HttpResponse> {
-    let headers = ResponseHeaders::new(&file, cache_control_directive)?;
+    let size = file.size();
+    let last_modified = file.last_modified()?.with_timezone(&Utc);
+    let headers = ResponseHeaders::new(&file, cache_control_directives)?;
     let builder = HttpResponseBuilder::new()
-        .header(http::header::CACHE_CONTROL, headers.cache_control)
-        .header(http::header::CONTENT_TYPE, headers.content_type)
-        .header(http::header::ETAG, headers.etag)
-        .header(http::header::LAST_MODIFIED, headers.last_modified);
+        .header(http::header::CACHE_CONTROL, &headers.cache_control)
+        .header(http::header::ETAG, &headers.etag)
+        .header(http::header::LAST_MODIFIED, &headers.last_modified);
+
+    match Precondition::evaluate(req_headers, &headers.etag, last_modified) {
+        Precondition::Passed => {}
+        Precondition::NotModified => {
+            return builder
+                .status(StatusCode::NOT_MODIFIED)
+                .body(full_body(Bytes::new()))
+                .context("Failed to build HTTP Not Modified Response");
+        }
+        Precondition::Failed => {
+            return builder
+                .status(StatusCode::PRECONDITION_FAILED)
+                .body(full_body(Bytes::new()))
+                .context("Failed to build HTTP Precondition Failed Response");
+        }
+    }
 
-    let body = Full::new(Bytes::from(file.bytes().await?));
-    let response = builder
-        .body(body)
-        .context("Failed to build HTTP File Response")?;
+    let range = RangeRequest::evaluate(
+        req_headers,
+        size,
+        Validators {
+            etag: Some(&headers.etag),
+            last_modified: Some(&headers.last_modified),
+        },
+    );
+    let builder = builder
+        .header(http::header::ACCEPT_RANGES, "bytes")
+        .header(http::header::CONTENT_TYPE, &headers.content_type);
 
-    Ok(response)
+    make_range_response(builder, file.file, size, &headers.content_type, range).await
 }
@@ -1,6 +1,7 @@
 mod directory_entry;
 mod file;
 mod http_utils;
+mod preconditions;
 mod query_params;
 mod scoped_file_system;
 
@@ -10,38 +11,51 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
-use bytes::Bytes;
 use chrono::{DateTime, Local};
+use globset::{GlobSet, GlobSetBuilder};
 use handlebars::{Handlebars, handlebars_helper};
 use http::response::Builder as HttpResponseBuilder;
-use http::{StatusCode, Uri};
-use http_body_util::Full;
+use http::{HeaderMap, StatusCode, Uri};
 use humansize::{DECIMAL, format_size};
 use percent_encoding::{percent_decode_str, utf8_percent_encode};
 
+use crate::config::CacheControl;
 use crate::handler::file_server::utils::url_encode::{PERCENT_ENCODE_SET, decode_uri, encode_uri};
-use crate::server::HttpResponse;
+use crate::server::{HttpResponse, full_body};
 
 use self::directory_entry::{BreadcrumbItem, DirectoryEntry, DirectoryIndex, Sort};
-use self::http_utils::{CacheControlDirective, make_http_file_response};
+use self::http_utils::make_http_file_response;
 use self::query_params::{QueryParams, SortBy};
 
 pub use file::File;
+pub use http_utils::CacheControlDirective;
 
 pub use scoped_file_system::{Entry, ScopedFileSystem};
 
 /// Explorer's Handlebars template filename
 const EXPLORER_TEMPLATE: &str = "explorer";
 
+/// `Cache-Control` directives for files not matching any rule
+const DEFAULT_CACHE_CONTROL: &[CacheControlDirective] = &[CacheControlDirective::MaxAge(2500)];
+
+/// `Cache-Control` directives for every file when caching is disabled
+const NO_CACHE: &[CacheControlDirective] = &[CacheControlDirective::NoCache];
+
 pub struct FileServerConfig {
     pub index: bool,
     pub root_dir: PathBuf,
+    /// Path prefix the file server is mounted at, used to build links in the
+    /// directory listing. Empty when mounted at `/`.
+    pub base_path: String,
     pub spa: bool,
+    pub cache_control: CacheControl,
 }
 
 pub struct FileServer {
     handlebars: Arc<Handlebars<'static>>,
     scoped_file_system: ScopedFileSystem,
+    /// Patterns of the `Cache-Control` rules, in the same order
+    cache_control_patterns: GlobSet,
     config: FileServerConfig,
 }
 
@@ -50,14 +64,45 @@ impl<'a> FileServer {
     pub fn new(config: FileServerConfig) -> Self {
         let handlebars = FileServer::make_handlebars_engine();
         let scoped_file_system = ScopedFileSystem::new(config.root_dir.clone()).unwrap();
+        let cache_control_patterns = config
+            .cache_control
+            .rules
+            .iter()
+            .fold(GlobSetBuilder::new(), |mut builder, rule| {
+                builder.add(rule.pattern.clone());
+                builder
+            })
+            .build()
+            .unwrap();
 
         FileServer {
             handlebars,
             scoped_file_system,
+            cache_control_patterns,
             config,
         }
     }
 
+    /// Retrieves the `Cache-Control` directives for the file at `path`, from
+    /// the first rule which pattern matches the path relative to `root_dir`.
+    fn cache_control(&self, path: &Path) -> &[CacheControlDirective] {
+        if self.config.cache_control.no_cache {
+            return NO_CACHE;
+        }
+
+        let path = path.strip_prefix(&self.config.root_dir).unwrap_or(path);
+
+        self.cache_control_patterns
+            .matches(path)
+            .first()
+            .map(|index| {
+                self.config.cache_control.rules[*index]
+                    .directives
+                    .as_slice()
+            })
+            .unwrap_or(DEFAULT_CACHE_CONTROL)
+    }
+
     /// Creates a new `Handlebars` instance with templates registered
     fn make_handlebars_engine() -> Arc<Handlebars<'a>> {
         let mut handlebars = Handlebars::new();
@@ -137,7 +182,10 @@ impl<'a> FileServer {
     ///
     /// If the matched path resolves to a file, attempts to render it if the
     /// MIME type is supported, otherwise returns the binary (downloadable file)
-    pub async fn resolve(&self, req_path: String) -> Result<HttpResponse> {
+    ///
+    /// Request `headers` are used to evaluate preconditions and to serve
+    /// byte ranges of files.
+    pub async fn resolve(&self, req_path: String, headers: &HeaderMap) -> Result<HttpResponse> {
         let (path, query_params) = FileServer::parse_path(req_path.as_str())?;
 
         match self.scoped_file_system.resolve(path).await {
@@ -148,13 +196,16 @@ impl<'a> FileServer {
 
                         filepath.push("index.html");
                         if let Ok(file) = tokio::fs::File::open(&filepath).await {
+                            let cache_control = self.cache_control(&filepath);
+
                             return make_http_file_response(
                                 File {
-                                    path: filepath,
                                     metadata: file.metadata().await?,
+                                    path: filepath,
                                     file,
                                 },
-                                CacheControlDirective::MaxAge(2500),
+                                cache_control,
+                                headers,
                             )
                             .await;
                         }
@@ -163,16 +214,20 @@ impl<'a> FileServer {
                     self.render_directory_index(dir.path(), query_params).await
                 }
                 Entry::File(file) => {
-                    make_http_file_response(*file, CacheControlDirective::MaxAge(2500)).await
+                    let cache_control = self.cache_control(&file.path);
+
+                    make_http_file_response(*file, cache_control, headers).await
                 }
             },
             Err(err) => {
                 if self.config.spa {
+                    let mut path = self.config.root_dir.clone();
+                    path.push("index.html");
+
+                    let cache_control = self.cache_control(&path);
+
                     return make_http_file_response(
                         {
-                            let mut path = self.config.root_dir.clone();
-                            path.push("index.html");
-
                             let file = tokio::fs::File::open(&path).await?;
 
                             let metadata = file.metadata().await?;
@@ -183,7 +238,8 @@ impl<'a> FileServer {
                                 file,
                             }
                         },
-                        CacheControlDirective::MaxAge(2500),
+                        cache_control,
+                        headers,
                     )
                     .await;
                 }
@@ -203,12 +259,10 @@ impl<'a> FileServer {
                 let response = hyper::Response::builder()
                     .status(status)
                     .header(http::header::CONTENT_TYPE, "text/html")
-                    .body(Full::new(Bytes::from(
-                        handlebars::Handlebars::new().render_template(
-                            include_str!("./template/error.hbs"),
-                            &serde_json::json!({"error": err.to_string(), "code": code}),
-                        )?,
-                    )))?;
+                    .body(full_body(handlebars::Handlebars::new().render_template(
+                        include_str!("./template/error.hbs"),
+                        &serde_json::json!({"error": err.to_string(), "code": code}),
+                    )?))?;
 
                 Ok(response)
             }
@@ -223,14 +277,18 @@ impl<'a> FileServer {
         path: PathBuf,
         query_params: Option<QueryParams>,
     ) -> Result<HttpResponse> {
-        let directory_index =
-            FileServer::index_directory(self.config.root_dir.clone(), path, query_params)?;
+        let directory_index = FileServer::index_directory(
+            self.config.root_dir.clone(),
+            &self.config.base_path,
+            path,
+            query_params,
+        )?;
         let html = self
             .handlebars
             .render(EXPLORER_TEMPLATE, &directory_index)
             .unwrap();
 
-        let body = Full::new(Bytes::from(html.as_bytes().to_vec()));
+        let body = full_body(html);
 
         Ok(HttpResponseBuilder::new()
             .header(http::header::CONTENT_TYPE, "text/html")
@@ -254,7 +312,11 @@ impl<'a> FileServer {
         utf8_percent_encode(component, PERCENT_ENCODE_SET).to_string()
     }
 
-    fn breadcrumbs_from_path(root_dir: &Path, path: &Path) -> Result<Vec<BreadcrumbItem>> {
+    fn breadcrumbs_from_path(
+        root_dir: &Path,
+        base_path: &str,
+        path: &Path,
+    ) -> Result<Vec<BreadcrumbItem>> {
         let root_dir_name = root_dir
             .components()
             .next_back()
@@ -276,7 +338,7 @@ impl<'a> FileServer {
                     .decode_utf8()
                     .expect("The path name is not UTF-8 compliant")
                     .to_string(),
-                entry_link: format!("/{}", stripped[0..=idx].join("/")),
+                entry_link: format!("{base_path}/{}", stripped[0..=idx].join("/")),
             })
             .collect::<Vec<BreadcrumbItem>>();
 
@@ -284,7 +346,7 @@ impl<'a> FileServer {
             0,
             BreadcrumbItem {
                 entry_name: String::from(root_dir_name),
-                entry_link: String::from("/"),
+                entry_link: format!("{base_path}/"),
             },
         );
 
@@ -295,10 +357,11 @@ impl<'a> FileServer {
     /// (HTTP Request URI)
     fn index_directory(
         root_dir: PathBuf,
+        base_path: &str,
         path: PathBuf,
         query_params: Option<QueryParams>,
     ) -> Result<DirectoryIndex> {
-        let breadcrumbs = FileServer::breadcrumbs_from_path(&root_dir, &path)?;
+        let breadcrumbs = FileServer::breadcrumbs_from_path(&root_dir, base_path, &path)?;
         let entries = read_dir(path).context("Unable to read directory")?;
         let mut directory_entries: Vec<DirectoryEntry> = Vec::new();
 
@@ -324,7 +387,7 @@ impl<'a> FileServer {
                     .to_string(),
                 is_dir: metadata.is_dir(),
                 size_bytes: metadata.len(),
-                entry_path: FileServer::make_dir_entry_link(&root_dir, &entry.path()),
+                entry_path: FileServer::make_dir_entry_link(&root_dir, base_path, &entry.path()),
                 date_created,
                 date_modified,
             });
@@ -376,9 +439,106 @@ impl<'a> FileServer {
     ///
     /// This happens because links should behave relative to the `/` path
     /// which in this case is `http-server/src` instead of system's root path.
-    fn make_dir_entry_link(root_dir: &Path, entry_path: &Path) -> String {
+    ///
+    /// Links are prefixed with `base_path` when the file server is mounted
+    /// under a path prefix.
+    fn make_dir_entry_link(root_dir: &Path, base_path: &str, entry_path: &Path) -> String {
         let path = entry_path.strip_prefix(root_dir).unwrap();
 
-        encode_uri(path)
+        format!("{base_path}{}", encode_uri(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use globset::Glob;
+    use tempfile::TempDir;
+
+    use super::{CacheControlDirective, DEFAULT_CACHE_CONTROL, FileServer, FileServerConfig};
+    use crate::config::{CacheControl, CacheControlRule};
+
+    fn file_server(root: &TempDir, cache_control: CacheControl) -> FileServer {
+        FileServer::new(FileServerConfig {
+            index: false,
+            root_dir: root.path().to_path_buf(),
+            base_path: String::new(),
+            spa: false,
+            cache_control,
+        })
+    }
+
+    fn rule(pattern: &str, directives: &[&str]) -> CacheControlRule {
+        CacheControlRule {
+            pattern: Glob::new(pattern).unwrap(),
+            directives: directives
+                .iter()
+                .map(|directive| CacheControlDirective::from_str(directive).unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn applies_first_matching_cache_control_rule() {
+        let root = TempDir::new().unwrap();
+        let file_server = file_server(
+            &root,
+            CacheControl {
+                no_cache: false,
+                rules: vec![
+                    rule("assets/**", &["public", "max-age=31536000", "immutable"]),
+                    rule("*.html", &["no-cache"]),
+                ],
+            },
+        );
+        let cache_control = |path: &str| file_server.cache_control(&root.path().join(path));
+
+        assert_eq!(
+            cache_control("assets/app.js"),
+            [
+                CacheControlDirective::Public,
+                CacheControlDirective::MaxAge(31536000),
+                CacheControlDirective::Immutable
+            ]
+        );
+        assert_eq!(
+            cache_control("assets/index.html"),
+            cache_control("assets/app.js")
+        );
+        assert_eq!(
+            cache_control("docs/index.html"),
+            [CacheControlDirective::NoCache]
+        );
+        assert_eq!(cache_control("app.wasm"), DEFAULT_CACHE_CONTROL);
+    }
+
+    #[test]
+    fn disables_cache_control() {
+        let root = TempDir::new().unwrap();
+        let file_server = file_server(
+            &root,
+            CacheControl {
+                no_cache: true,
+                rules: vec![rule("*", &["max-age=60"])],
+            },
+        );
+
+        assert_eq!(
+            file_server.cache_control(&root.path().join("index.html")),
+            [CacheControlDirective::NoCache]
+        );
+    }
+
+    #[test]
+    fn parses_cache_control_directives() {
+        let directive = |s: &str| CacheControlDirective::from_str(s).unwrap();
+
+        assert_eq!(directive("Max-Age=60"), CacheControlDirective::MaxAge(60));
+        assert_eq!(directive(" s-maxage=0 ").to_string(), "s-maxage=0");
+        assert_eq!(directive("no-store").to_string(), "no-store");
+        assert!(CacheControlDirective::from_str("max-age").is_err());
+        assert!(CacheControlDirective::from_str("max-age=-1").is_err());
+        assert!(CacheControlDirective::from_str("stale-while-revalidate=60").is_err());
     }
 }
@@ -0,0 +1,209 @@
+//! Conditional requests as described in [RFC 9110 Section 13][1].
+//!
+//! Preconditions are evaluated from the file metadata alone, so files are
+//! never read when the client already holds the current version or when a
+//! precondition fails.
+//!
+//! [1]: https://datatracker.ietf.org/doc/html/rfc9110#section-13
+use chrono::{DateTime, NaiveDateTime, Utc};
+use http::header::{IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE};
+use http::{HeaderMap, HeaderName};
+
+/// Outcome of evaluating the preconditions of a `GET` request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Precondition {
+    /// Serve the file
+    Passed,
+    /// Respond with `304 Not Modified`
+    NotModified,
+    /// Respond with `412 Precondition Failed`
+    Failed,
+}
+
+impl Precondition {
+    /// Evaluates the precondition headers of a `GET` request in the order
+    /// defined by RFC 9110 Section 13.2.2, against the `etag` and the
+    /// `last_modified` date of the file.
+    pub fn evaluate(headers: &HeaderMap, etag: &str, last_modified: DateTime<Utc>) -> Self {
+        if let Some(if_match) = header_str(headers, IF_MATCH) {
+            if !matches_any(if_match, etag, strong_eq) {
+                return Precondition::Failed;
+            }
+        } else if let Some(since) = header_date(headers, IF_UNMODIFIED_SINCE)
+            && last_modified.timestamp() > since.timestamp()
+        {
+            return Precondition::Failed;
+        }
+
+        if let Some(if_none_match) = header_str(headers, IF_NONE_MATCH) {
+            if matches_any(if_none_match, etag, weak_eq) {
+                return Precondition::NotModified;
+            }
+        } else if let Some(since) = header_date(headers, IF_MODIFIED_SINCE)
+            && last_modified.timestamp() <= since.timestamp()
+        {
+            return Precondition::NotModified;
+        }
+
+        Precondition::Passed
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Retrieves a header holding an HTTP date, invalid dates are ignored as
+/// required by RFC 9110.
+fn header_date(headers: &HeaderMap, name: HeaderName) -> Option<DateTime<Utc>> {
+    header_str(headers, name).and_then(parse_http_date)
+}
+
+/// Parses an HTTP date in the preferred IMF-fixdate format, or any of the
+/// obsolete RFC 850 and asctime formats.
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    ["%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|date| date.and_utc())
+}
+
+/// Checks whether `etag` matches any of the entity tags listed in
+/// `condition`, or `condition` is `*`.
+fn matches_any(condition: &str, etag: &str, eq: fn(&str, &str) -> bool) -> bool {
+    if condition.trim() == "*" {
+        return true;
+    }
+
+    entity_tags(condition).any(|tag| eq(tag, etag))
+}
+
+/// Iterates over a comma separated list of entity tags, such as
+/// `"a", W/"b"`. Tags are quoted and may contain commas.
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    let mut rest = list;
+
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+
+        let start = rest.find('"')?;
+        let end = start + 1 + rest[start + 1..].find('"')?;
+        let tag = &rest[..=end];
+
+        rest = &rest[end + 1..];
+        Some(tag)
+    })
+}
+
+/// Strong comparison, both tags must be strong and identical.
+fn strong_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && !b.starts_with("W/") && a == b
+}
+
+/// Weak comparison, opaque tags must be identical regardless of either tag
+/// being weak.
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+    use http::HeaderMap;
+
+    use super::{Precondition, parse_http_date};
+
+    const ETAG: &str = "W/\"1f-5f3c.0\"";
+
+    fn last_modified() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 7, 1, 10, 0, 0).unwrap()
+    }
+
+    fn evaluate(headers: &[(&'static str, &str)]) -> Precondition {
+        let mut map = HeaderMap::new();
+
+        for (name, value) in headers {
+            map.insert(*name, value.parse().unwrap());
+        }
+
+        Precondition::evaluate(&map, ETAG, last_modified())
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        for date in [
+            "Tue, 01 Jul 2025 10:00:00 GMT",
+            "Tuesday, 01-Jul-25 10:00:00 GMT",
+            "Tue Jul  1 10:00:00 2025",
+        ] {
+            assert_eq!(parse_http_date(date), Some(last_modified()), "{date}");
+        }
+
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn evaluates_if_none_match() {
+        assert_eq!(
+            evaluate(&[("if-none-match", "\"1f-5f3c.0\"")]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate(&[("if-none-match", "\"other\", W/\"1f-5f3c.0\"")]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate(&[("if-none-match", "*")]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate(&[("if-none-match", "\"other\"")]),
+            Precondition::Passed
+        );
+        // If-Modified-Since is ignored when If-None-Match is present
+        assert_eq!(
+            evaluate(&[
+                ("if-none-match", "\"other\""),
+                ("if-modified-since", "Tue, 01 Jul 2025 10:00:00 GMT")
+            ]),
+            Precondition::Passed
+        );
+    }
+
+    #[test]
+    fn evaluates_if_modified_since() {
+        assert_eq!(
+            evaluate(&[("if-modified-since", "Tue, 01 Jul 2025 10:00:00 GMT")]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate(&[("if-modified-since", "Mon, 30 Jun 2025 10:00:00 GMT")]),
+            Precondition::Passed
+        );
+        assert_eq!(
+            evaluate(&[("if-modified-since", "invalid")]),
+            Precondition::Passed
+        );
+    }
+
+    #[test]
+    fn evaluates_if_match() {
+        assert_eq!(evaluate(&[("if-match", "*")]), Precondition::Passed);
+        // Weak entity tags never match using the strong comparison
+        assert_eq!(evaluate(&[("if-match", ETAG)]), Precondition::Failed);
+        assert_eq!(
+            evaluate(&[("if-unmodified-since", "Mon, 30 Jun 2025 10:00:00 GMT")]),
+            Precondition::Failed
+        );
+        assert_eq!(
+            evaluate(&[("if-unmodified-since", "Tue, 01 Jul 2025 10:00:00 GMT")]),
+            Precondition::Passed
+        );
+    }
+}
@@ -1,8 +1,17 @@
 pub mod file_explorer;
 pub mod file_server;
+pub mod proxy;
+pub mod range;
+pub mod router;
+pub mod virtual_host;
 
-use anyhow::Result;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{Error, Result};
 use async_trait::async_trait;
+use futures::future::BoxFuture;
+use tower::{Service, ServiceExt};
 
 use crate::server::{HttpRequest, HttpResponse};
 
@@ -10,3 +19,54 @@ use crate::server::{HttpRequest, HttpResponse};
 pub trait Handler: Send + Sync {
     async fn handle(&self, req: HttpRequest) -> Result<HttpResponse>;
 }
+
+/// Exposes a `Handler` as a `tower::Service` so it can be wrapped by layers.
+#[derive(Clone)]
+pub struct HandlerService {
+    handler: Arc<dyn Handler>,
+}
+
+impl HandlerService {
+    pub fn new(handler: Arc<dyn Handler>) -> Self {
+        Self { handler }
+    }
+}
+
+impl Service<HttpRequest> for HandlerService {
+    type Response = HttpResponse;
+    type Error = Error;
+    type Future = BoxFuture<'static, Result<HttpResponse>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: HttpRequest) -> Self::Future {
+        let handler = Arc::clone(&self.handler);
+
+        Box::pin(async move { handler.handle(req).await })
+    }
+}
+
+/// Exposes a layered `tower::Service` back as a `Handler`, so middleware
+/// can be applied to a single `Handler` such as a route.
+pub struct ServiceHandler<S> {
+    service: S,
+}
+
+impl<S> ServiceHandler<S> {
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait]
+impl<S> Handler for ServiceHandler<S>
+where
+    S: Service<HttpRequest, Response = HttpResponse, Error = Error> + Clone + Send + Sync,
+    S::Future: Send,
+{
+    async fn handle(&self, req: HttpRequest) -> Result<HttpResponse> {
+        self.service.clone().oneshot(req).await
+    }
+}
@@ -0,0 +1,251 @@
+//! Reverse proxy forwarding requests to an upstream HTTP server.
+//!
+//! Request and response bodies are streamed as they are received. The
+//! `Host` header is rewritten to the upstream authority and the original
+//! client details are forwarded through the `X-Forwarded-For`,
+//! `X-Forwarded-Proto` and `X-Forwarded-Host` headers.
+use anyhow::{Context, Error, Result};
+use async_trait::async_trait;
+use http::header::{AUTHORIZATION, CONNECTION, HOST, TE, TRAILER, TRANSFER_ENCODING, UPGRADE};
+use http::uri::PathAndQuery;
+use http::{Extensions, HeaderMap, HeaderName, HeaderValue, Response, StatusCode, Uri, Version};
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use tracing::warn;
+
+use crate::config::Upstream;
+use crate::handler::Handler;
+use crate::layer::basic_auth::AuthenticatedUser;
+use crate::server::{ConnectionInfo, HttpRequest, HttpResponse, full_body};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// Headers meaningful only for a single connection which must not be
+/// forwarded by proxies, as listed in RFC 9110 Section 7.6.1.
+const HOP_BY_HOP_HEADERS: [HeaderName; 8] = [
+    CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    HeaderName::from_static("proxy-authenticate"),
+    HeaderName::from_static("proxy-authorization"),
+    TE,
+    TRAILER,
+    TRANSFER_ENCODING,
+];
+
+pub struct Proxy {
+    upstream: Upstream,
+    client: Client<HttpConnector, Incoming>,
+}
+
+impl Proxy {
+    pub fn new(upstream: Upstream) -> Self {
+        Self {
+            upstream,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+
+    /// Builds the upstream URI for the request `uri`, prepending the
+    /// upstream path to the request path.
+    fn upstream_uri(&self, uri: &Uri) -> Result<Uri> {
+        let upstream = self.upstream.uri();
+        let base_path = upstream.path().trim_end_matches('/');
+        let path_and_query = match uri.path_and_query() {
+            Some(path_and_query) => format!("{base_path}{path_and_query}"),
+            None => format!("{base_path}/"),
+        };
+        let mut parts = upstream.clone().into_parts();
+
+        parts.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+
+        Uri::from_parts(parts).context("Failed to build upstream URI")
+    }
+
+    /// Removes hop-by-hop headers, including the ones listed in the
+    /// `Connection` header.
+    fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+        let listed = headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+            .collect::<Vec<_>>();
+
+        for name in listed.iter().chain(HOP_BY_HOP_HEADERS.iter()) {
+            headers.remove(name);
+        }
+
+        headers.remove(UPGRADE);
+    }
+
+    /// Removes the `Authorization` header of requests authenticated by the
+    /// basic auth layer, as such credentials belong to this server rather than
+    /// to the upstream. `Proxy-Authorization` is removed as a hop-by-hop
+    /// header.
+    fn remove_credentials(headers: &mut HeaderMap, extensions: &Extensions) {
+        if extensions.get::<AuthenticatedUser>().is_some() {
+            headers.remove(AUTHORIZATION);
+        }
+    }
+
+    /// Appends the client details to the `X-Forwarded-*` headers.
+    fn add_forwarded_headers(req: &mut HttpRequest) {
+        let info = req.extensions().get::<ConnectionInfo>().cloned();
+        let host = req
+            .uri()
+            .authority()
+            .map(|authority| authority.as_str().to_owned())
+            .or_else(|| {
+                req.headers()
+                    .get(HOST)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from)
+            });
+        let headers = req.headers_mut();
+
+        if let Some(info) = info {
+            let client_ip = info.remote_addr.ip().to_string();
+            let forwarded_for = match headers
+                .get(X_FORWARDED_FOR)
+                .and_then(|value| value.to_str().ok())
+            {
+                Some(forwarded_for) => format!("{forwarded_for}, {client_ip}"),
+                None => client_ip,
+            };
+            let proto = if info.tls { "https" } else { "http" };
+
+            if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+                headers.insert(X_FORWARDED_FOR, value);
+            }
+
+            headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+        }
+
+        if let Some(value) = host.and_then(|host| HeaderValue::from_str(&host).ok()) {
+            headers.insert(X_FORWARDED_HOST, value);
+        }
+    }
+
+    fn bad_gateway() -> HttpResponse {
+        let mut response = Response::new(full_body("Bad Gateway"));
+        *response.status_mut() = StatusCode::BAD_GATEWAY;
+        response
+    }
+}
+
+#[async_trait]
+impl Handler for Proxy {
+    async fn handle(&self, mut req: HttpRequest) -> Result<HttpResponse> {
+        let uri = self.upstream_uri(req.uri())?;
+
+        Self::add_forwarded_headers(&mut req);
+        Self::remove_hop_by_hop_headers(req.headers_mut());
+
+        let (mut parts, body) = req.into_parts();
+        Self::remove_credentials(&mut parts.headers, &parts.extensions);
+        let mut req = HttpRequest::from_parts(parts, body);
+
+        if let Some(authority) = uri.authority() {
+            req.headers_mut()
+                .insert(HOST, HeaderValue::from_str(authority.as_str())?);
+        }
+
+        // Requests received over HTTP/2 are forwarded over HTTP/1.1
+        *req.uri_mut() = uri;
+        *req.version_mut() = Version::HTTP_11;
+
+        let response = match self.client.request(req).await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(%err, upstream = %self.upstream.uri(), "Failed to reach upstream");
+                return Ok(Self::bad_gateway());
+            }
+        };
+
+        let (mut parts, body) = response.into_parts();
+
+        Self::remove_hop_by_hop_headers(&mut parts.headers);
+        parts.version = Version::default();
+
+        Ok(Response::from_parts(
+            parts,
+            body.map_err(Error::from).boxed(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use http::{Extensions, HeaderMap, HeaderValue, Uri};
+
+    use super::Proxy;
+    use crate::config::Upstream;
+    use crate::layer::basic_auth::AuthenticatedUser;
+
+    #[test]
+    fn builds_upstream_uri() {
+        let proxy = Proxy::new(Upstream::from_str("http://127.0.0.1:3000").unwrap());
+        let uri = Uri::from_static("/users?page=2");
+
+        assert_eq!(
+            proxy.upstream_uri(&uri).unwrap(),
+            "http://127.0.0.1:3000/users?page=2"
+        );
+
+        let proxy = Proxy::new(Upstream::from_str("http://127.0.0.1:3000/api/").unwrap());
+
+        assert_eq!(
+            proxy.upstream_uri(&uri).unwrap(),
+            "http://127.0.0.1:3000/api/users?page=2"
+        );
+    }
+
+    #[test]
+    fn removes_hop_by_hop_headers() {
+        let mut headers = HeaderMap::new();
+
+        headers.insert("connection", HeaderValue::from_static("close, x-custom"));
+        headers.insert("x-custom", HeaderValue::from_static("1"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+        Proxy::remove_hop_by_hop_headers(&mut headers);
+
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key("accept"));
+    }
+
+    #[test]
+    fn removes_consumed_credentials() {
+        let mut headers = HeaderMap::new();
+        let mut extensions = Extensions::new();
+
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("Basic dXNlcjpwYXNz"),
+        );
+        headers.insert(
+            "proxy-authorization",
+            HeaderValue::from_static("Basic cHJveHk="),
+        );
+        Proxy::remove_hop_by_hop_headers(&mut headers);
+        Proxy::remove_credentials(&mut headers, &extensions);
+
+        // Credentials meant for the upstream are forwarded
+        assert!(headers.contains_key("authorization"));
+        assert!(!headers.contains_key("proxy-authorization"));
+
+        extensions.insert(AuthenticatedUser(String::from("user")));
+        Proxy::remove_credentials(&mut headers, &extensions);
+
+        assert!(headers.is_empty());
+    }
+}
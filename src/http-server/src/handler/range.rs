@@ -0,0 +1,395 @@
+//! Byte range requests as described in [RFC 9110 Section 14][1], shared by
+//! the file server and the file explorer.
+//!
+//! A single satisfiable range is answered with `206 Partial Content` and a
+//! `Content-Range` header, multiple ranges with a `multipart/byteranges`
+//! body, and ranges outside of the file with `416 Range Not Satisfiable`.
+//! Malformed `Range` headers are ignored and the whole file is served.
+//!
+//! [1]: https://datatracker.ietf.org/doc/html/rfc9110#section-14
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::SeekFrom;
+
+use anyhow::{Context, Error, Result};
+use async_stream::try_stream;
+use bytes::Bytes;
+use chrono::{DateTime, Local, Utc};
+use futures::{Stream, TryStreamExt};
+use http::header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, IF_RANGE, RANGE};
+use http::response::Builder;
+use http::{HeaderMap, HeaderValue, StatusCode};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::server::{HttpBody, HttpResponse, full_body};
+
+/// Size of the chunks files are read and sent in, which bounds the memory
+/// used by each response regardless of the file size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Maximum number of ranges served in a single response, requests with more
+/// ranges (after merging overlapping ones) are served the whole file.
+const MAX_RANGES: usize = 32;
+
+/// Inclusive range of bytes within a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{size}", self.start, self.end)
+    }
+}
+
+/// Response extension marking responses streaming the contents of a file,
+/// which count as transfers for the rate limiter.
+#[derive(Clone, Copy, Debug)]
+pub struct FileTransfer;
+
+/// Validators of the served file, compared against `If-Range`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Validators<'a> {
+    pub etag: Option<&'a str>,
+    pub last_modified: Option<&'a str>,
+}
+
+impl Validators<'_> {
+    /// Builds the entity tag of a file from its size and modification time.
+    pub fn etag(size: u64, last_modified: &DateTime<Local>) -> String {
+        format!(
+            "W/\"{0:x}-{1:x}.{2:x}\"",
+            size,
+            last_modified.timestamp(),
+            last_modified.timestamp_subsec_nanos(),
+        )
+    }
+
+    /// Formats the modification time of a file as sent in `Last-Modified`.
+    pub fn last_modified(last_modified: &DateTime<Local>) -> String {
+        format!(
+            "{} GMT",
+            last_modified
+                .with_timezone(&Utc)
+                .format("%a, %d %b %Y %H:%M:%S")
+        )
+    }
+}
+
+/// Portion of a file requested through the `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// Serve the whole file
+    Full,
+    /// Serve the provided ranges, sorted and without overlaps
+    Partial(Vec<ByteRange>),
+    /// None of the requested ranges overlap the file
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Evaluates the `Range` and `If-Range` headers for a file of `size`
+    /// bytes.
+    pub fn evaluate(headers: &HeaderMap, size: u64, validators: Validators<'_>) -> Self {
+        let Some(range) = headers.get(RANGE).and_then(|value| value.to_str().ok()) else {
+            return RangeRequest::Full;
+        };
+
+        if let Some(if_range) = headers.get(IF_RANGE) {
+            let matches = if_range
+                .to_str()
+                .is_ok_and(|if_range| Self::if_range_matches(if_range.trim(), validators));
+
+            if !matches {
+                return RangeRequest::Full;
+            }
+        }
+
+        Self::parse(range, size)
+    }
+
+    /// Checks an `If-Range` value against the file validators. Entity tags
+    /// must match using the strong comparison, so weak tags never match, and
+    /// dates must match `Last-Modified` exactly.
+    fn if_range_matches(if_range: &str, validators: Validators<'_>) -> bool {
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            return !if_range.starts_with("W/") && validators.etag == Some(if_range);
+        }
+
+        validators.last_modified == Some(if_range)
+    }
+
+    /// Parses a `Range` header value such as `bytes=0-99,200-,-50`.
+    fn parse(range: &str, size: u64) -> Self {
+        let Some((unit, specs)) = range.split_once('=') else {
+            return RangeRequest::Full;
+        };
+
+        if !unit.trim().eq_ignore_ascii_case("bytes") {
+            return RangeRequest::Full;
+        }
+
+        let mut ranges = Vec::new();
+
+        for spec in specs
+            .split(',')
+            .map(str::trim)
+            .filter(|spec| !spec.is_empty())
+        {
+            let Some((first, last)) = spec.split_once('-') else {
+                return RangeRequest::Full;
+            };
+
+            let range = match (first.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(start), Ok(end)) if start <= end => (start < size).then(|| ByteRange {
+                    start,
+                    end: end.min(size - 1),
+                }),
+                (Ok(start), Err(_)) if last.is_empty() => (start < size).then(|| ByteRange {
+                    start,
+                    end: size - 1,
+                }),
+                (Err(_), Ok(suffix)) if first.is_empty() => {
+                    (suffix > 0 && size > 0).then(|| ByteRange {
+                        start: size.saturating_sub(suffix),
+                        end: size - 1,
+                    })
+                }
+                _ => return RangeRequest::Full,
+            };
+
+            ranges.extend(range);
+        }
+
+        if ranges.is_empty() {
+            return if specs.trim().is_empty() {
+                RangeRequest::Full
+            } else {
+                RangeRequest::Unsatisfiable
+            };
+        }
+
+        let ranges = Self::merge(ranges);
+
+        if ranges.len() > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+
+        RangeRequest::Partial(ranges)
+    }
+
+    /// Sorts `ranges` and merges the ones overlapping or adjacent to each
+    /// other.
+    fn merge(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end.saturating_add(1) => {
+                    last.end = last.end.max(range.end);
+                }
+                _ => merged.push(range),
+            }
+        }
+
+        merged
+    }
+}
+
+/// Completes the response for `file` according to the `RangeRequest`,
+/// streaming its contents in chunks of up to `CHUNK_SIZE` bytes.
+///
+/// The `builder` is expected to carry the headers describing the file, such
+/// as `Content-Type`, `ETag` or `Last-Modified`.
+pub async fn make_range_response(
+    builder: Builder,
+    mut file: File,
+    size: u64,
+    content_type: &str,
+    range: RangeRequest,
+) -> Result<HttpResponse> {
+    let response = match range {
+        RangeRequest::Full => builder
+            .extension(FileTransfer)
+            .header(CONTENT_LENGTH, size)
+            .body(stream_file(file, size)),
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+
+            file.seek(SeekFrom::Start(range.start)).await?;
+
+            builder
+                .extension(FileTransfer)
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, range.content_range(size))
+                .header(CONTENT_LENGTH, range.len())
+                .body(stream_file(file, range.len()))
+        }
+        RangeRequest::Partial(ranges) => {
+            let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+            let parts = ranges
+                .into_iter()
+                .map(|range| {
+                    let header = format!(
+                        "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+                        range.content_range(size)
+                    );
+
+                    (Bytes::from(header), range)
+                })
+                .collect::<Vec<_>>();
+            let closing = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+            let content_length = parts
+                .iter()
+                .map(|(header, range)| header.len() as u64 + range.len())
+                .sum::<u64>()
+                + closing.len() as u64;
+            let mut builder = builder
+                .extension(FileTransfer)
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_LENGTH, content_length);
+
+            // The parts carry the file `Content-Type`, the response is a
+            // multipart message instead
+            if let Some(headers) = builder.headers_mut() {
+                headers.insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_str(&format!("multipart/byteranges; boundary={boundary}"))?,
+                );
+            }
+
+            let stream = multipart_stream(file, parts, closing).map_ok(Frame::data);
+
+            builder.body(StreamBody::new(stream).boxed())
+        }
+        RangeRequest::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(CONTENT_RANGE, format!("bytes */{size}"))
+            .body(full_body(Bytes::new())),
+    };
+
+    response.context("Failed to build HTTP Range Response")
+}
+
+/// Streams `len` bytes of `file` starting from its current position.
+fn stream_file(file: File, len: u64) -> HttpBody {
+    let stream = ReaderStream::with_capacity(file.take(len), CHUNK_SIZE)
+        .map_ok(Frame::data)
+        .map_err(Error::from);
+
+    StreamBody::new(stream).boxed()
+}
+
+/// Streams the `multipart/byteranges` body made of each part header followed
+/// by the bytes of its range, and the `closing` delimiter.
+fn multipart_stream(
+    mut file: File,
+    parts: Vec<(Bytes, ByteRange)>,
+    closing: Bytes,
+) -> impl Stream<Item = Result<Bytes>> + Send + Sync {
+    try_stream! {
+        for (header, range) in parts {
+            yield header;
+
+            file.seek(SeekFrom::Start(range.start)).await?;
+
+            let mut chunks = ReaderStream::with_capacity((&mut file).take(range.len()), CHUNK_SIZE);
+
+            while let Some(chunk) = chunks.try_next().await? {
+                yield chunk;
+            }
+        }
+
+        yield closing;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+    use http::header::{IF_RANGE, RANGE};
+
+    use super::{ByteRange, RangeRequest, Validators};
+
+    fn evaluate(range: &str, size: u64) -> RangeRequest {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(RANGE, range.parse().unwrap());
+        RangeRequest::evaluate(&headers, size, Validators::default())
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let range = |start, end| ByteRange { start, end };
+
+        assert_eq!(
+            evaluate("bytes=0-99", 1000),
+            RangeRequest::Partial(vec![range(0, 99)])
+        );
+        assert_eq!(
+            evaluate("bytes=900-", 1000),
+            RangeRequest::Partial(vec![range(900, 999)])
+        );
+        assert_eq!(
+            evaluate("bytes=-100", 1000),
+            RangeRequest::Partial(vec![range(900, 999)])
+        );
+        assert_eq!(
+            evaluate("bytes=500-2000", 1000),
+            RangeRequest::Partial(vec![range(500, 999)])
+        );
+        assert_eq!(
+            evaluate("bytes=500-599, 0-9, 550-649, 10-19", 1000),
+            RangeRequest::Partial(vec![range(0, 19), range(500, 649)])
+        );
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(evaluate("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(evaluate("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(evaluate("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_invalid_ranges() {
+        assert_eq!(evaluate("bytes=9-1", 1000), RangeRequest::Full);
+        assert_eq!(evaluate("items=0-1", 1000), RangeRequest::Full);
+        assert_eq!(evaluate("bytes=a-b", 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn evaluates_if_range() {
+        let validators = Validators {
+            etag: Some("\"abc\""),
+            last_modified: Some("Tue, 01 Jul 2025 10:00:00 GMT"),
+        };
+        let evaluate = |if_range: &str| {
+            let mut headers = HeaderMap::new();
+
+            headers.insert(RANGE, "bytes=0-0".parse().unwrap());
+            headers.insert(IF_RANGE, if_range.parse().unwrap());
+            RangeRequest::evaluate(&headers, 10, validators)
+        };
+
+        assert!(matches!(evaluate("\"abc\""), RangeRequest::Partial(_)));
+        assert!(matches!(
+            evaluate("Tue, 01 Jul 2025 10:00:00 GMT"),
+            RangeRequest::Partial(_)
+        ));
+        assert_eq!(evaluate("W/\"abc\""), RangeRequest::Full);
+        assert_eq!(evaluate("\"xyz\""), RangeRequest::Full);
+    }
+}
@@ -0,0 +1,115 @@
+//! Routing table dispatching requests to `Handler`s by path prefix.
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use http::uri::PathAndQuery;
+use http::{Response, StatusCode, Uri};
+
+use crate::handler::Handler;
+use crate::server::{HttpRequest, HttpResponse, full_body};
+
+pub struct Router {
+    /// Routes sorted by prefix length in descending order so the most
+    /// specific prefix is matched first
+    routes: Vec<(String, Arc<dyn Handler>)>,
+}
+
+impl Router {
+    pub fn new(routes: Vec<(String, Arc<dyn Handler>)>) -> Self {
+        let mut routes = routes
+            .into_iter()
+            .map(|(prefix, handler)| (Self::normalize_prefix(&prefix), handler))
+            .collect::<Vec<_>>();
+
+        routes.sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+
+        Self { routes }
+    }
+
+    /// Normalizes a prefix to start with a slash and have no trailing slash,
+    /// the root prefix `/` is represented as an empty string.
+    pub fn normalize_prefix(prefix: &str) -> String {
+        let prefix = prefix.trim_matches('/');
+
+        if prefix.is_empty() {
+            return String::new();
+        }
+
+        format!("/{prefix}")
+    }
+
+    /// Strips `prefix` from `path` if `path` belongs to it, matching whole
+    /// path segments only (`/static` matches `/static/app.js` but not
+    /// `/statics`).
+    fn strip_prefix<'a>(prefix: &str, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(prefix)?;
+
+        if rest.is_empty() {
+            return Some("/");
+        }
+
+        if rest.starts_with('/') {
+            return Some(rest);
+        }
+
+        None
+    }
+
+    /// Replaces the path of `uri` with `path`, keeping the query string.
+    fn rewrite_uri(uri: &Uri, path: &str) -> Result<Uri> {
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_string(),
+        };
+        let mut parts = uri.clone().into_parts();
+
+        parts.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+
+        Uri::from_parts(parts).context("Failed to rewrite request URI")
+    }
+}
+
+#[async_trait]
+impl Handler for Router {
+    async fn handle(&self, mut req: HttpRequest) -> Result<HttpResponse> {
+        for (prefix, handler) in &self.routes {
+            if let Some(path) = Self::strip_prefix(prefix, req.uri().path()) {
+                if !prefix.is_empty() {
+                    *req.uri_mut() = Self::rewrite_uri(req.uri(), path)?;
+                }
+
+                return handler.handle(req).await;
+            }
+        }
+
+        let mut response = Response::new(full_body("Not Found"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Router;
+
+    #[test]
+    fn normalizes_prefixes() {
+        assert_eq!(Router::normalize_prefix("/"), "");
+        assert_eq!(Router::normalize_prefix("static/"), "/static");
+        assert_eq!(Router::normalize_prefix("/a/b/"), "/a/b");
+    }
+
+    #[test]
+    fn strips_whole_segments() {
+        assert_eq!(Router::strip_prefix("", "/app.js"), Some("/app.js"));
+        assert_eq!(Router::strip_prefix("/static", "/static"), Some("/"));
+        assert_eq!(
+            Router::strip_prefix("/static", "/static/app.js"),
+            Some("/app.js")
+        );
+        assert_eq!(Router::strip_prefix("/static", "/statics/app.js"), None);
+        assert_eq!(Router::strip_prefix("/static", "/"), None);
+    }
+}
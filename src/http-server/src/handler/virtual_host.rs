@@ -0,0 +1,262 @@
+//! Name-based virtual hosts dispatching requests to `Handler`s by the host
+//! name they are addressed to.
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Error, Result, bail};
+use async_trait::async_trait;
+use http::Request;
+use http::header::HOST;
+use serde::Deserialize;
+
+use crate::handler::Handler;
+use crate::server::{ConnectionInfo, HttpRequest, HttpResponse};
+
+/// A host name pattern matched against the `Host` header and TLS SNI.
+///
+/// Patterns are compared case-insensitively. A leading `*.` matches any
+/// subdomain, so `*.example.com` matches `docs.example.com` but not
+/// `example.com` itself. IPv6 addresses, with or without brackets, match the
+/// same address in any notation.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct HostPattern(String);
+
+impl HostPattern {
+    /// Checks whether `host`, without port, matches this pattern.
+    pub fn matches(&self, host: &str) -> bool {
+        if let Some(addr) = Self::ipv6(host) {
+            return addr.to_string() == self.0;
+        }
+
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+        match self.0.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+            None => host == self.0,
+        }
+    }
+
+    /// Parses an IPv6 address, optionally enclosed in brackets.
+    fn ipv6(host: &str) -> Option<Ipv6Addr> {
+        let host = host.trim();
+        let addr = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+
+        Ipv6Addr::from_str(addr).ok()
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(addr) = Self::ipv6(s) {
+            return Ok(HostPattern(addr.to_string()));
+        }
+
+        let pattern = s.trim().trim_end_matches('.').to_ascii_lowercase();
+        let name = pattern.strip_prefix("*.").unwrap_or(&pattern);
+
+        if name.is_empty() || name.contains(['*', '/', ':', ' ']) {
+            bail!("Invalid host name \"{s}\".");
+        }
+
+        Ok(HostPattern(pattern))
+    }
+}
+
+impl TryFrom<String> for HostPattern {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::from_str(&value)
+    }
+}
+
+pub struct VirtualHosts {
+    /// Virtual hosts in configuration order, the first one matching the
+    /// request host name is used
+    hosts: Vec<(Vec<HostPattern>, Arc<dyn Handler>)>,
+    /// Handler for requests not matching any virtual host
+    default: Arc<dyn Handler>,
+}
+
+impl VirtualHosts {
+    pub fn new(
+        hosts: Vec<(Vec<HostPattern>, Arc<dyn Handler>)>,
+        default: Arc<dyn Handler>,
+    ) -> Self {
+        Self { hosts, default }
+    }
+
+    /// Retrieves the host name a request is addressed to from the URI
+    /// authority (HTTP/2), the `Host` header or the TLS SNI, in that order.
+    fn host_name<B>(req: &Request<B>) -> Option<&str> {
+        if let Some(host) = req.uri().host() {
+            return Some(host);
+        }
+
+        if let Some(host) = req
+            .headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+        {
+            return Some(Self::strip_port(host));
+        }
+
+        req.extensions()
+            .get::<ConnectionInfo>()
+            .and_then(|info| info.server_name.as_deref())
+    }
+
+    /// Removes the port from a `Host` header value, including the brackets
+    /// enclosing IPv6 addresses.
+    fn strip_port(host: &str) -> &str {
+        if let Some(rest) = host.strip_prefix('[') {
+            return rest.split_once(']').map_or(rest, |(addr, _)| addr);
+        }
+
+        host.split_once(':').map_or(host, |(name, _)| name)
+    }
+
+    /// Selects the handler of the first virtual host matching the request
+    /// host name, or the default one.
+    fn select<B>(&self, req: &Request<B>) -> &Arc<dyn Handler> {
+        Self::host_name(req)
+            .and_then(|host| {
+                self.hosts
+                    .iter()
+                    .find(|(patterns, _)| patterns.iter().any(|pattern| pattern.matches(host)))
+            })
+            .map_or(&self.default, |(_, handler)| handler)
+    }
+}
+
+#[async_trait]
+impl Handler for VirtualHosts {
+    async fn handle(&self, req: HttpRequest) -> Result<HttpResponse> {
+        self.select(&req).handle(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use http::Request;
+    use http::header::HOST;
+
+    use super::{HostPattern, VirtualHosts};
+    use crate::handler::Handler;
+    use crate::server::{HttpRequest, HttpResponse};
+
+    struct Unreachable;
+
+    #[async_trait]
+    impl Handler for Unreachable {
+        async fn handle(&self, _: HttpRequest) -> Result<HttpResponse> {
+            unreachable!()
+        }
+    }
+
+    fn pattern(s: &str) -> HostPattern {
+        HostPattern::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn strips_port() {
+        assert_eq!(VirtualHosts::strip_port("example.com"), "example.com");
+        assert_eq!(VirtualHosts::strip_port("example.com:8080"), "example.com");
+        assert_eq!(VirtualHosts::strip_port("[::1]:8080"), "::1");
+        assert_eq!(VirtualHosts::strip_port("[::1]"), "::1");
+    }
+
+    #[test]
+    fn matches_host_patterns() {
+        let exact = HostPattern::from_str("Example.com").unwrap();
+        let wildcard = HostPattern::from_str("*.example.com").unwrap();
+
+        assert!(exact.matches("example.com"));
+        assert!(exact.matches("EXAMPLE.COM."));
+        assert!(!exact.matches("www.example.com"));
+        assert!(wildcard.matches("www.example.com"));
+        assert!(wildcard.matches("a.b.example.com"));
+        assert!(!wildcard.matches("example.com"));
+        assert!(!wildcard.matches("badexample.com"));
+
+        let loopback = HostPattern::from_str("[::1]").unwrap();
+
+        assert!(loopback.matches("::1"));
+        assert!(loopback.matches("[0::1]"));
+        assert!(!loopback.matches("::2"));
+        assert!(!loopback.matches("localhost"));
+        assert!(
+            HostPattern::from_str("fe80::1")
+                .unwrap()
+                .matches("[FE80::1]")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_host_patterns() {
+        for s in [
+            "",
+            "*.",
+            "*",
+            "a*.example.com",
+            "example.com/docs",
+            "example.com:80",
+        ] {
+            assert!(HostPattern::from_str(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn selects_virtual_host_by_host_name() {
+        let docs: Arc<dyn Handler> = Arc::new(Unreachable);
+        let www: Arc<dyn Handler> = Arc::new(Unreachable);
+        let default: Arc<dyn Handler> = Arc::new(Unreachable);
+        let virtual_hosts = VirtualHosts::new(
+            vec![
+                (vec![pattern("docs.example.com")], Arc::clone(&docs)),
+                (
+                    vec![pattern("example.com"), pattern("*.example.com")],
+                    Arc::clone(&www),
+                ),
+            ],
+            Arc::clone(&default),
+        );
+
+        for (host, expected) in [
+            ("docs.example.com", &docs),
+            ("DOCS.example.com:8080", &docs),
+            ("api.example.com", &www),
+            ("example.com", &www),
+            ("example.org", &default),
+        ] {
+            let request = Request::builder().header(HOST, host).body(()).unwrap();
+
+            assert!(
+                Arc::ptr_eq(virtual_hosts.select(&request), expected),
+                "{host}"
+            );
+        }
+
+        let request = Request::builder()
+            .uri("https://docs.example.com/")
+            .header(HOST, "example.com")
+            .body(())
+            .unwrap();
+
+        assert!(Arc::ptr_eq(virtual_hosts.select(&request), &docs));
+    }
+}
@@ -0,0 +1,456 @@
+//! Closes connections left open by clients without any activity.
+//!
+//! A connection is idle when no request is in progress and no data has been
+//! received nor sent for the configured time. Requests in progress keep the
+//! connection alive, as handlers such as the proxy may take a while before
+//! sending a response.
+//!
+//! Connections must also start a request within the request head timeout
+//! whenever none is in progress, whatever the protocol. Bytes trickled by a
+//! client without ever completing a request head, such as HTTP/2 frames, do
+//! not keep the connection alive.
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use http::Response;
+use hyper::body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep, sleep};
+use tower::{Layer, Service};
+
+#[derive(Default)]
+struct Counters {
+    /// Requests waiting for their response
+    pending: AtomicUsize,
+    /// Requests whose response body has not been sent yet
+    active: AtomicUsize,
+    /// Requests started on the connection so far
+    started: AtomicU64,
+}
+
+/// Requests in progress on a connection, counted by the layer until their
+/// response is ready and until their response body is sent.
+#[derive(Clone, Default)]
+pub struct Requests(Arc<Counters>);
+
+impl Requests {
+    /// Marks a request as in progress until the returned guards are dropped.
+    fn start(&self) -> (PendingGuard, ActiveGuard) {
+        self.0.pending.fetch_add(1, Ordering::Relaxed);
+        self.0.active.fetch_add(1, Ordering::Relaxed);
+        self.0.started.fetch_add(1, Ordering::Relaxed);
+
+        (
+            PendingGuard(Arc::clone(&self.0)),
+            ActiveGuard(Arc::clone(&self.0)),
+        )
+    }
+
+    fn is_pending(&self) -> bool {
+        self.0.pending.load(Ordering::Relaxed) > 0
+    }
+
+    fn is_active(&self) -> bool {
+        self.0.active.load(Ordering::Relaxed) > 0
+    }
+
+    fn started(&self) -> u64 {
+        self.0.started.load(Ordering::Relaxed)
+    }
+}
+
+impl<S> Layer<S> for Requests {
+    type Service = RequestsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestsService {
+            inner,
+            requests: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestsService<S> {
+    inner: S,
+    requests: Requests,
+}
+
+impl<S, R, B> Service<R> for RequestsService<S>
+where
+    S: Service<R, Response = Response<B>>,
+{
+    type Response = Response<RequestBody<B>>;
+    type Error = S::Error;
+    type Future = RequestFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let (pending, active) = self.requests.start();
+
+        RequestFuture {
+            inner: self.inner.call(req),
+            pending: Some(pending),
+            active: Some(active),
+        }
+    }
+}
+
+struct PendingGuard(Arc<Counters>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.0.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct ActiveGuard(Arc<Counters>);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pin_project! {
+    /// Response future keeping its request counted until it is dropped.
+    pub struct RequestFuture<F> {
+        #[pin]
+        inner: F,
+        pending: Option<PendingGuard>,
+        active: Option<ActiveGuard>,
+    }
+}
+
+impl<F, B, E> Future for RequestFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<RequestBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = std::task::ready!(this.inner.poll(cx));
+
+        this.pending.take();
+
+        let active = this.active.take();
+
+        Poll::Ready(result.map(|response| response.map(|inner| RequestBody { inner, active })))
+    }
+}
+
+pin_project! {
+    /// Response body keeping its request active until it is dropped.
+    pub struct RequestBody<B> {
+        #[pin]
+        inner: B,
+        active: Option<ActiveGuard>,
+    }
+}
+
+impl<B: Body> Body for RequestBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.project().inner.poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Deadline for a request to start while none is active.
+struct HeadDeadline {
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+    /// Requests started when the deadline was last reset
+    started: u64,
+}
+
+/// Stream failing with `TimedOut` once the connection has been idle for the
+/// idle timeout, or without any request for the request head timeout.
+pub struct IdleTimeout<T> {
+    inner: T,
+    timeout: Option<Duration>,
+    deadline: Pin<Box<Sleep>>,
+    head: Option<HeadDeadline>,
+    requests: Requests,
+}
+
+impl<T> IdleTimeout<T> {
+    /// Wraps the `inner` stream, closing it after `timeout` without activity
+    /// and after `head_timeout` without any request, disabled when `None`.
+    pub fn new(
+        inner: T,
+        timeout: Option<Duration>,
+        head_timeout: Option<Duration>,
+        requests: Requests,
+    ) -> Self {
+        // The idle deadline is never polled when disabled
+        let deadline = Box::pin(sleep(timeout.unwrap_or(Duration::MAX / 2)));
+        let head = head_timeout.map(|timeout| HeadDeadline {
+            timeout,
+            sleep: Box::pin(sleep(timeout)),
+            started: requests.started(),
+        });
+
+        Self {
+            inner,
+            timeout,
+            deadline,
+            head,
+            requests,
+        }
+    }
+
+    fn touch(&mut self) {
+        if let Some(timeout) = self.timeout {
+            self.deadline.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+
+    /// Checks whether a request was started in time, registering the task to
+    /// be woken when the deadline elapses.
+    fn poll_head(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(head) = &mut self.head else {
+            return Poll::Pending;
+        };
+        let started = self.requests.started();
+
+        if started != head.started || self.requests.is_active() {
+            head.started = started;
+            head.sleep.as_mut().reset(Instant::now() + head.timeout);
+        }
+
+        if head.sleep.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "request head timeout",
+        )))
+    }
+
+    /// Checks the deadline once the stream is pending, registering the task
+    /// to be woken when it elapses.
+    fn poll_idle<R>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<R>> {
+        if self.timeout.is_none() || self.deadline.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        if self.requests.is_pending() {
+            self.touch();
+            let _ = self.deadline.as_mut().poll(cx);
+
+            return Poll::Pending;
+        }
+
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "connection idle timeout",
+        )))
+    }
+
+    fn on_poll<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<R>>,
+    ) -> Poll<io::Result<R>> {
+        if let Poll::Ready(Err(err)) = self.poll_head(cx) {
+            return Poll::Ready(Err(err));
+        }
+
+        match poll {
+            Poll::Pending => self.poll_idle(cx),
+            ready => {
+                self.touch();
+                ready
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for IdleTimeout<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+        this.on_poll(cx, poll)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for IdleTimeout<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+
+        this.on_poll(cx, poll)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+
+        this.on_poll(cx, poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+    use tokio::time::{sleep, timeout};
+
+    use super::{IdleTimeout, Requests};
+
+    const IDLE: Duration = Duration::from_secs(60);
+    const HEAD: Duration = Duration::from_secs(30);
+
+    fn connection(
+        idle: Option<Duration>,
+        head: Option<Duration>,
+    ) -> (IdleTimeout<DuplexStream>, DuplexStream, Requests) {
+        let (server, client) = duplex(64);
+        let requests = Requests::default();
+
+        (
+            IdleTimeout::new(server, idle, head, requests.clone()),
+            client,
+            requests,
+        )
+    }
+
+    /// Sends a byte every second without ever completing a request.
+    fn trickle(mut client: DuplexStream) {
+        tokio::spawn(async move {
+            while client.write_all(b"a").await.is_ok() {
+                sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
+
+    /// Reads from the stream until it fails.
+    async fn read_to_error(stream: &mut IdleTimeout<DuplexStream>) -> io::Error {
+        let mut buf = [0; 64];
+
+        loop {
+            if let Err(err) = stream.read(&mut buf).await {
+                return err;
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closes_idle_connections() {
+        let (mut stream, _client, _) = connection(Some(IDLE), None);
+        let err = read_to_error(&mut stream).await;
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "connection idle timeout");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_connections_with_pending_requests() {
+        let (mut stream, _client, requests) = connection(Some(IDLE), None);
+        let guards = requests.start();
+
+        assert!(timeout(IDLE * 3, read_to_error(&mut stream)).await.is_err());
+
+        drop(guards);
+
+        let err = read_to_error(&mut stream).await;
+
+        assert_eq!(err.to_string(), "connection idle timeout");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closes_connections_without_requests() {
+        let (mut stream, client, _) = connection(Some(IDLE), Some(HEAD));
+
+        trickle(client);
+
+        let err = timeout(HEAD * 2, read_to_error(&mut stream)).await.unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "request head timeout");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_connections_sending_responses() {
+        let (mut stream, client, requests) = connection(None, Some(HEAD));
+        let (pending, active) = requests.start();
+
+        // The response is ready but its body has not been sent yet
+        drop(pending);
+        trickle(client);
+
+        assert!(timeout(HEAD * 3, read_to_error(&mut stream)).await.is_err());
+
+        drop(active);
+
+        let err = timeout(HEAD * 2, read_to_error(&mut stream)).await.unwrap();
+
+        assert_eq!(err.to_string(), "request head timeout");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resets_head_timeout_on_new_requests() {
+        let (mut stream, client, requests) = connection(None, Some(HEAD));
+
+        trickle(client);
+
+        for _ in 0..3 {
+            assert!(timeout(HEAD / 2, read_to_error(&mut stream)).await.is_err());
+            drop(requests.start());
+        }
+
+        let err = timeout(HEAD * 2, read_to_error(&mut stream)).await.unwrap();
+
+        assert_eq!(err.to_string(), "request head timeout");
+    }
+}
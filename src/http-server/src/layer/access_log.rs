@@ -0,0 +1,576 @@
+//! Access log recording a line for every request, in the Common Log Format,
+//! the Combined Log Format or as JSON objects.
+//!
+//! Entries are written once the response body has been sent, or dropped when
+//! the client goes away, so the bytes sent and the duration cover the whole
+//! transfer.
+//!
+//! Writing to the log file may block, so entries are handed to a dedicated
+//! writer thread. Entries are dropped rather than slowing requests down when
+//! the writer falls behind.
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write, stdout};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result};
+use bytes::{Buf, Bytes};
+use chrono::{DateTime, Local};
+use futures::future::BoxFuture;
+use http::header::{REFERER, USER_AGENT};
+use http::{HeaderMap, HeaderName, Request, Response, StatusCode};
+use hyper::body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use serde::Serialize;
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::config::{AccessLogConfig, AccessLogFormat};
+use crate::layer::basic_auth::AuthenticatedUser;
+use crate::server::ClientIp;
+
+/// Number of entries waiting to be written before further entries are
+/// dropped.
+const QUEUE_SIZE: usize = 4096;
+
+enum Command {
+    Write(String),
+    /// Write further entries to the provided file
+    Reopen(File),
+    /// Stop once pending entries are written
+    Close,
+}
+
+/// Destination of access log entries.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    path: Option<PathBuf>,
+    sender: SyncSender<Command>,
+    /// Entries dropped since the writer last reported them
+    dropped: Arc<AtomicU64>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AccessLog {
+    pub fn new(config: &AccessLogConfig) -> Result<Self> {
+        let file = config.path.as_deref().map(Self::open).transpose()?;
+        let (sender, receiver) = sync_channel(QUEUE_SIZE);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = {
+            let dropped = Arc::clone(&dropped);
+
+            thread::Builder::new()
+                .name(String::from("access-log"))
+                .spawn(move || run_writer(file, receiver, &dropped))
+                .context("Failed to start access log writer")?
+        };
+
+        Ok(Self {
+            format: config.format(),
+            path: config.path.clone(),
+            sender,
+            dropped,
+            writer: Mutex::new(Some(writer)),
+        })
+    }
+
+    /// Reopens the log file, so entries are written to a new file once the
+    /// current one has been moved away, such as by `logrotate`.
+    pub fn reopen(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = Self::open(path)?;
+
+        self.sender
+            .send(Command::Reopen(file))
+            .context("Access log writer stopped")
+    }
+
+    fn open(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open access log file: {}", path.display()))
+    }
+
+    fn write(&self, entry: &Entry) {
+        let line = match self.format {
+            AccessLogFormat::Common => entry.common(),
+            AccessLogFormat::Combined => entry.combined(),
+            AccessLogFormat::Json => match serde_json::to_string(entry) {
+                Ok(line) => line,
+                Err(err) => {
+                    warn!(%err, "Failed to serialize access log entry");
+                    return;
+                }
+            },
+        };
+
+        match self.sender.try_send(Command::Write(line)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => warn!("Access log writer stopped"),
+        }
+    }
+}
+
+impl Drop for AccessLog {
+    /// Waits for pending entries to be written.
+    fn drop(&mut self) {
+        if self.sender.send(Command::Close).is_err() {
+            return;
+        }
+
+        if let Some(writer) = self.writer.get_mut().ok().and_then(Option::take)
+            && writer.join().is_err()
+        {
+            warn!("Access log writer panicked");
+        }
+    }
+}
+
+/// Writes entries received through `receiver` to `file`, or standard output
+/// when `None`, until closed.
+fn run_writer(file: Option<File>, receiver: Receiver<Command>, dropped: &AtomicU64) {
+    let output = |file: Option<File>| -> BufWriter<Box<dyn Write>> {
+        match file {
+            Some(file) => BufWriter::new(Box::new(file)),
+            None => BufWriter::new(Box::new(stdout())),
+        }
+    };
+    let mut writer = output(file);
+
+    let mut closed = false;
+
+    while !closed && let Ok(command) = receiver.recv() {
+        // Entries queued meanwhile are written at once before flushing
+        for command in std::iter::once(command).chain(receiver.try_iter()) {
+            match command {
+                Command::Write(mut line) => {
+                    line.push('\n');
+
+                    if let Err(err) = writer.write_all(line.as_bytes()) {
+                        warn!(%err, "Failed to write access log entry");
+                    }
+                }
+                Command::Reopen(file) => {
+                    if let Err(err) = writer.flush() {
+                        warn!(%err, "Failed to write access log entry");
+                    }
+
+                    writer = output(Some(file));
+                }
+                Command::Close => closed = true,
+            }
+        }
+
+        if let Err(err) = writer.flush() {
+            warn!(%err, "Failed to write access log entry");
+        }
+
+        let count = dropped.swap(0, Ordering::Relaxed);
+
+        if count > 0 {
+            warn!(
+                count,
+                "Dropped access log entries, the log is written too slowly"
+            );
+        }
+    }
+}
+
+/// Details of a request and its response.
+#[derive(Debug, Serialize)]
+struct Entry {
+    time: DateTime<Local>,
+    remote_addr: Option<IpAddr>,
+    /// Username verified by HTTP Basic Authentication
+    user: Option<String>,
+    method: String,
+    /// Request path including the query string
+    path: String,
+    protocol: String,
+    status: u16,
+    /// Response body bytes sent to the client
+    bytes: u64,
+    duration_ms: f64,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl Entry {
+    fn new<B>(req: &Request<B>) -> Self {
+        let headers = req.headers();
+
+        Self {
+            time: Local::now(),
+            remote_addr: req.extensions().get::<ClientIp>().map(|ClientIp(ip)| *ip),
+            user: None,
+            method: req.method().to_string(),
+            path: req
+                .uri()
+                .path_and_query()
+                .map(|path| path.to_string())
+                .unwrap_or_else(|| req.uri().path().to_string()),
+            protocol: format!("{:?}", req.version()),
+            status: 0,
+            bytes: 0,
+            duration_ms: 0.,
+            referer: header(headers, REFERER),
+            user_agent: header(headers, USER_AGENT),
+        }
+    }
+
+    /// Completes the entry with the status of the `response` and the user
+    /// authenticated to produce it, if any.
+    fn set_response<B>(&mut self, response: &Response<B>) {
+        self.status = response.status().as_u16();
+        self.user = response
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|AuthenticatedUser(user)| user.clone());
+    }
+
+    fn set_duration(&mut self, duration: Duration) {
+        self.duration_ms = (duration.as_secs_f64() * 1_000_000.).round() / 1_000.;
+    }
+
+    /// Formats the entry as `host ident authuser [date] "request" status bytes`.
+    fn common(&self) -> String {
+        format!(
+            "{} - {} [{}] \"{} {} {}\" {} {}",
+            self.remote_addr
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|| String::from("-")),
+            self.user
+                .as_deref()
+                .map(escape)
+                .unwrap_or_else(|| "-".into()),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            escape(&self.method),
+            escape(&self.path),
+            self.protocol,
+            self.status,
+            match self.bytes {
+                0 => String::from("-"),
+                bytes => bytes.to_string(),
+            },
+        )
+    }
+
+    /// Formats the entry in the Common Log Format followed by the quoted
+    /// `Referer` and `User-Agent` headers.
+    fn combined(&self) -> String {
+        format!(
+            "{} \"{}\" \"{}\"",
+            self.common(),
+            self.referer
+                .as_deref()
+                .map(escape)
+                .unwrap_or_else(|| "-".into()),
+            self.user_agent
+                .as_deref()
+                .map(escape)
+                .unwrap_or_else(|| "-".into()),
+        )
+    }
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+/// Escapes quotes, backslashes and control characters so values provided by
+/// clients cannot forge entries or break quoted fields.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for char in value.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            char if char.is_control() => escaped.push_str(&format!("\\x{:02x}", char as u32)),
+            char => escaped.push(char),
+        }
+    }
+
+    escaped
+}
+
+/// Writes the `entry` to the `log` when dropped, along with the time elapsed
+/// since `start`.
+struct Recorder {
+    log: Arc<AccessLog>,
+    entry: Entry,
+    start: Instant,
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.entry.set_duration(self.start.elapsed());
+        self.log.write(&self.entry);
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    log: Option<Arc<AccessLog>>,
+}
+
+impl AccessLogLayer {
+    /// Creates the layer, requests are not logged when `log` is `None`.
+    pub fn new(log: Option<Arc<AccessLog>>) -> Self {
+        Self { log }
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogService {
+            inner,
+            log: self.log.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLogService<S> {
+    inner: S,
+    log: Option<Arc<AccessLog>>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AccessLogService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<AccessLogBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let recorder = self.log.as_ref().map(|log| Recorder {
+            log: Arc::clone(log),
+            entry: Entry::new(&req),
+            start: Instant::now(),
+        });
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            match future.await {
+                Ok(response) => {
+                    let recorder = recorder.map(|mut recorder| {
+                        recorder.entry.set_response(&response);
+                        recorder
+                    });
+
+                    Ok(response.map(|inner| AccessLogBody { inner, recorder }))
+                }
+                Err(err) => {
+                    // The connection is closed without a response
+                    if let Some(mut recorder) = recorder {
+                        recorder.entry.status = StatusCode::INTERNAL_SERVER_ERROR.as_u16();
+                    }
+
+                    Err(err)
+                }
+            }
+        })
+    }
+}
+
+pin_project! {
+    /// Response body counting the bytes sent, the entry is logged once the
+    /// body is dropped.
+    pub struct AccessLogBody<B> {
+        #[pin]
+        inner: B,
+        recorder: Option<Recorder>,
+    }
+}
+
+impl<B> Body for AccessLogBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = this.inner.poll_frame(cx);
+
+        if let (Poll::Ready(Some(Ok(frame))), Some(recorder)) = (&frame, this.recorder)
+            && let Some(data) = frame.data_ref()
+        {
+            recorder.entry.bytes += data.remaining() as u64;
+        }
+
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+    use std::sync::Arc;
+
+    use anyhow::Error;
+    use chrono::{Local, TimeZone};
+    use http::header::AUTHORIZATION;
+    use http::{Request, Response};
+    use http_body_util::BodyExt;
+    use tempfile::TempDir;
+    use tower::{Layer, ServiceExt, service_fn};
+
+    use super::{AccessLog, AccessLogLayer, Entry, escape};
+    use crate::config::{AccessLogConfig, AccessLogFormat};
+    use crate::layer::basic_auth::AuthenticatedUser;
+    use crate::server::{HttpResponse, full_body};
+
+    fn entry() -> Entry {
+        Entry {
+            time: Local.with_ymd_and_hms(2025, 7, 1, 10, 0, 0).unwrap(),
+            remote_addr: Some("192.0.2.1".parse().unwrap()),
+            user: Some(String::from("john")),
+            method: String::from("GET"),
+            path: String::from("/index.html?q=\"a\""),
+            protocol: String::from("HTTP/1.1"),
+            status: 200,
+            bytes: 512,
+            duration_ms: 1.5,
+            referer: None,
+            user_agent: Some(String::from("curl/8.0")),
+        }
+    }
+
+    #[test]
+    fn escapes_values() {
+        assert_eq!(escape("plain value"), "plain value");
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(escape("a\nb\u{1b}"), "a\\x0ab\\x1b");
+    }
+
+    #[test]
+    fn formats_common_log_format() {
+        let entry = entry();
+        let time = entry.time.format("%d/%b/%Y:%H:%M:%S %z");
+
+        assert_eq!(
+            entry.common(),
+            format!("192.0.2.1 - john [{time}] \"GET /index.html?q=\\\"a\\\" HTTP/1.1\" 200 512")
+        );
+
+        let entry = Entry {
+            remote_addr: None,
+            user: None,
+            bytes: 0,
+            ..entry
+        };
+
+        assert!(entry.common().starts_with("- - - ["), "{}", entry.common());
+        assert!(entry.common().ends_with(" 200 -"), "{}", entry.common());
+    }
+
+    #[test]
+    fn formats_combined_log_format() {
+        let entry = entry();
+
+        assert_eq!(
+            entry.combined(),
+            format!("{} \"-\" \"curl/8.0\"", entry.common())
+        );
+    }
+
+    #[test]
+    fn formats_json() {
+        let entry: serde_json::Value = serde_json::to_value(entry()).unwrap();
+
+        assert_eq!(entry["remote_addr"], "192.0.2.1");
+        assert_eq!(entry["user"], "john");
+        assert_eq!(entry["path"], "/index.html?q=\"a\"");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["referer"], serde_json::Value::Null);
+    }
+
+    async fn log_request(authorization: &str, user: Option<&str>) -> String {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let log = Arc::new(
+            AccessLog::new(&AccessLogConfig {
+                enabled: Some(true),
+                format: Some(AccessLogFormat::Common),
+                path: Some(path.clone()),
+            })
+            .unwrap(),
+        );
+        let user = user.map(|user| AuthenticatedUser(user.to_string()));
+        let service = AccessLogLayer::new(Some(log)).layer(service_fn(move |_: Request<()>| {
+            let user = user.clone();
+
+            async move {
+                let mut response = Response::new(full_body("ok"));
+
+                if let Some(user) = user {
+                    response.extensions_mut().insert(user);
+                }
+
+                Ok::<HttpResponse, Error>(response)
+            }
+        }));
+        let request = Request::builder()
+            .header(AUTHORIZATION, authorization)
+            .body(())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        // The entry is written once the body is sent and the log once the
+        // last reference to it is dropped
+        response.into_body().collect().await.unwrap();
+
+        read_to_string(path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn logs_authenticated_users_only() {
+        // "forged:secret", not verified by any layer
+        let line = log_request("Basic Zm9yZ2VkOnNlY3JldA==", None).await;
+
+        assert!(line.starts_with("- - - ["), "{line}");
+        assert!(line.ends_with(" 200 2\n"), "{line}");
+
+        let line = log_request("Basic am9objphcHBsZXNlZWQ=", Some("john")).await;
+
+        assert!(line.starts_with("- - john ["), "{line}");
+    }
+}
@@ -0,0 +1,223 @@
+//! HTTP Basic Authentication as described in [RFC 7617][1].
+//!
+//! Requests missing valid credentials in the `Authorization` header are
+//! rejected with `401 Unauthorized` and a `WWW-Authenticate` challenge,
+//! without reaching the inner service. Authorized requests and their
+//! responses carry the `AuthenticatedUser` extension.
+//!
+//! [1]: https://datatracker.ietf.org/doc/html/rfc7617
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures::future::{BoxFuture, Either, Ready, ready};
+use http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use http::{HeaderMap, Request, Response, StatusCode};
+use subtle::ConstantTimeEq;
+use tower::{Layer, Service};
+
+use crate::config::BasicAuth;
+use crate::server::{HttpResponse, full_body};
+
+const CHALLENGE: &str = "Basic realm=\"http-server\", charset=\"UTF-8\"";
+
+/// Username of the credentials verified by the layer, available as a request
+/// extension to inner services and as a response extension to outer layers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedUser(pub String);
+
+#[derive(Clone)]
+pub struct BasicAuthLayer {
+    credentials: Arc<[u8]>,
+}
+
+impl BasicAuthLayer {
+    pub fn new(basic_auth: &BasicAuth) -> Self {
+        let credentials = format!("{}:{}", basic_auth.username, basic_auth.password);
+
+        Self {
+            credentials: credentials.into_bytes().into(),
+        }
+    }
+}
+
+impl<S> Layer<S> for BasicAuthLayer {
+    type Service = BasicAuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BasicAuthService {
+            inner,
+            credentials: Arc::clone(&self.credentials),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BasicAuthService<S> {
+    inner: S,
+    credentials: Arc<[u8]>,
+}
+
+impl<S> BasicAuthService<S> {
+    /// Checks the `Authorization` header against the configured credentials,
+    /// returning the authenticated user when they match.
+    ///
+    /// Decoded credentials are compared in constant time to avoid leaking
+    /// how many bytes matched through response timing.
+    fn authorize(&self, headers: &HeaderMap) -> Option<AuthenticatedUser> {
+        let decoded = decode_credentials(headers)?;
+
+        if !bool::from(decoded.ct_eq(&self.credentials)) {
+            return None;
+        }
+
+        let credentials = String::from_utf8_lossy(&decoded);
+        let (username, _) = credentials.split_once(':')?;
+
+        Some(AuthenticatedUser(username.to_string()))
+    }
+}
+
+/// Decodes the `username:password` credentials of the `Authorization`
+/// header using the `Basic` scheme.
+fn decode_credentials(headers: &HeaderMap) -> Option<Vec<u8>> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+        .and_then(|(_, encoded)| STANDARD.decode(encoded.trim()).ok())
+}
+
+impl<S, B> Service<Request<B>> for BasicAuthService<S>
+where
+    S: Service<Request<B>, Response = HttpResponse>,
+    S::Future: Send + 'static,
+{
+    type Response = HttpResponse;
+    type Error = S::Error;
+    type Future = Either<
+        BoxFuture<'static, Result<HttpResponse, S::Error>>,
+        Ready<Result<HttpResponse, S::Error>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        if let Some(user) = self.authorize(req.headers()) {
+            req.extensions_mut().insert(user.clone());
+
+            let future = self.inner.call(req);
+
+            return Either::Left(Box::pin(async move {
+                let mut response = future.await?;
+
+                response.extensions_mut().insert(user);
+
+                Ok(response)
+            }));
+        }
+
+        let response = Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(WWW_AUTHENTICATE, CHALLENGE)
+            .body(full_body("Unauthorized"))
+            .expect("Failed to build Unauthorized response");
+
+        Either::Right(ready(Ok(response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+    use http::{Request, Response, StatusCode};
+    use tower::{Layer, ServiceExt, service_fn};
+
+    use super::{AuthenticatedUser, BasicAuthLayer, CHALLENGE};
+    use crate::config::BasicAuth;
+    use crate::server::{HttpResponse, full_body};
+
+    async fn call(authorization: Option<&str>) -> HttpResponse {
+        let layer = BasicAuthLayer::new(&BasicAuth {
+            username: String::from("john"),
+            password: String::from("appleseed"),
+        });
+        let service = layer.layer(service_fn(|req: Request<()>| async move {
+            // The inner service sees the user verified by the layer
+            let user = req.extensions().get::<AuthenticatedUser>().cloned();
+
+            assert_eq!(user, Some(AuthenticatedUser(String::from("john"))));
+
+            Ok::<HttpResponse, Error>(Response::new(full_body("ok")))
+        }));
+        let mut request = Request::builder();
+
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+
+        service.oneshot(request.body(()).unwrap()).await.unwrap()
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    fn assert_unauthorized(response: &HttpResponse) {
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], CHALLENGE);
+        assert!(response.extensions().get::<AuthenticatedUser>().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_credentials() {
+        assert_unauthorized(&call(None).await);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_credentials() {
+        assert_unauthorized(&call(Some("Basic")).await);
+        assert_unauthorized(&call(Some("Basic not-base64!")).await);
+        assert_unauthorized(
+            &call(Some(&format!(
+                "Bearer {}",
+                STANDARD.encode("john:appleseed")
+            )))
+            .await,
+        );
+        assert_unauthorized(&call(Some(&basic("johnappleseed"))).await);
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_credentials() {
+        assert_unauthorized(&call(Some(&basic("john:wrong"))).await);
+        assert_unauthorized(&call(Some(&basic("jane:appleseed"))).await);
+        assert_unauthorized(&call(Some(&basic("john:appleseed2"))).await);
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_credentials() {
+        let authorization = basic("john:appleseed");
+
+        for authorization in [
+            authorization.clone(),
+            authorization.replace("Basic", "basic"),
+        ] {
+            let response = call(Some(&authorization)).await;
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key(WWW_AUTHENTICATE));
+            assert_eq!(
+                response.extensions().get::<AuthenticatedUser>(),
+                Some(&AuthenticatedUser(String::from("john")))
+            );
+        }
+    }
+}
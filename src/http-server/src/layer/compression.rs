@@ -0,0 +1,203 @@
+//! Response compression negotiated through the `Accept-Encoding` header.
+//!
+//! Encoding selection, including `q` values, and the `Vary` header are
+//! handled by `tower_http`'s `CompressionLayer`. This module decides which
+//! responses are worth compressing: small bodies, and formats which are
+//! already compressed such as images, audio, video or archives, are sent
+//! as is.
+use anyhow::Error;
+use http::{Response, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::Body;
+use tower::layer::util::Stack;
+use tower::util::MapResponseLayer;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::{CompressionBody, CompressionLayer};
+
+use crate::config::{CompressionConfig, ContentEncoding};
+use crate::server::{HttpBody, HttpResponse};
+
+/// MIME types which are already compressed. Matched as prefixes of the
+/// `Content-Type` header.
+const COMPRESSED_CONTENT_TYPES: [&str; 11] = [
+    "application/gzip",
+    "application/zip",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-rar-compressed",
+    "application/x-xz",
+    "audio/",
+    "video/",
+    "font/woff",
+    "text/event-stream",
+];
+
+/// `CompressionLayer` boxing compressed bodies back into a `HttpBody`, so
+/// responses have the same type whether compression is enabled or not.
+pub type BoxCompressionLayer = Stack<
+    CompressionLayer<ShouldCompress>,
+    MapResponseLayer<fn(Response<CompressionBody<HttpBody>>) -> HttpResponse>,
+>;
+
+/// Creates a `CompressionLayer` with the encodings enabled in
+/// `CompressionConfig`, `None` when compression is disabled.
+///
+/// The layer answers `406 Not Acceptable` to clients refusing every encoding
+/// it offers, including `identity`, so it is left out entirely rather than
+/// installed with every encoding turned off.
+pub fn make_compression_layer(config: &CompressionConfig) -> Option<BoxCompressionLayer> {
+    if !config.is_enabled() {
+        return None;
+    }
+
+    let compression = CompressionLayer::new()
+        .gzip(config.is_offered(ContentEncoding::Gzip))
+        .br(config.is_offered(ContentEncoding::Br))
+        .zstd(config.is_offered(ContentEncoding::Zstd))
+        .compress_when(ShouldCompress::new(config.min_size()));
+
+    Some(Stack::new(compression, MapResponseLayer::new(box_body)))
+}
+
+fn box_body(response: Response<CompressionBody<HttpBody>>) -> HttpResponse {
+    response.map(|body| body.map_err(Error::from_boxed).boxed())
+}
+
+#[derive(Clone, Debug)]
+pub struct ShouldCompress {
+    min_size: SizeAbove,
+    excluded: Vec<NotForContentType>,
+}
+
+impl ShouldCompress {
+    fn new(min_size: u64) -> Self {
+        let mut excluded = vec![NotForContentType::GRPC, NotForContentType::IMAGES];
+
+        excluded.extend(
+            COMPRESSED_CONTENT_TYPES
+                .into_iter()
+                .map(NotForContentType::const_new),
+        );
+
+        Self {
+            min_size: SizeAbove::new(min_size),
+            excluded,
+        }
+    }
+}
+
+impl Predicate for ShouldCompress {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: Body,
+    {
+        // Ranges refer to the identity encoding of the file
+        response.status() != StatusCode::PARTIAL_CONTENT
+            && self.min_size.should_compress(response)
+            && self
+                .excluded
+                .iter()
+                .all(|predicate| predicate.should_compress(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY};
+    use http::{Request, Response, StatusCode};
+    use tower::{ServiceBuilder, ServiceExt, service_fn};
+
+    use super::make_compression_layer;
+    use crate::config::{CompressionConfig, ContentEncoding};
+    use crate::server::{HttpResponse, full_body};
+
+    fn enabled() -> CompressionConfig {
+        CompressionConfig {
+            enabled: Some(true),
+            min_size: Some(16),
+            ..CompressionConfig::default()
+        }
+    }
+
+    async fn respond(
+        config: &CompressionConfig,
+        accept_encoding: &str,
+        content_type: &'static str,
+        len: usize,
+    ) -> HttpResponse {
+        let service = ServiceBuilder::new()
+            .option_layer(make_compression_layer(config))
+            .service(service_fn(move |_: Request<()>| async move {
+                Ok::<_, Error>(
+                    Response::builder()
+                        .header(CONTENT_TYPE, content_type)
+                        .body(full_body("a".repeat(len)))
+                        .unwrap(),
+                )
+            }));
+        let request = Request::builder()
+            .header(ACCEPT_ENCODING, accept_encoding)
+            .body(())
+            .unwrap();
+
+        service.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn compresses_responses() {
+        let response = respond(&enabled(), "gzip", "text/html", 64).await;
+
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[VARY], "accept-encoding");
+    }
+
+    #[tokio::test]
+    async fn offers_configured_encodings() {
+        let config = CompressionConfig {
+            encodings: Some(vec![ContentEncoding::Br, ContentEncoding::Gzip]),
+            ..enabled()
+        };
+        let response = respond(&config, "zstd, gzip;q=0.5", "text/html", 64).await;
+
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+
+        let response = respond(&config, "zstd", "text/html", 64).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn skips_small_responses() {
+        let response = respond(&enabled(), "gzip", "text/html", 8).await;
+
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn skips_compressed_formats() {
+        for content_type in ["image/png", "video/mp4", "application/zip"] {
+            let response = respond(&enabled(), "gzip", content_type, 64).await;
+
+            assert!(
+                !response.headers().contains_key(CONTENT_ENCODING),
+                "{content_type}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn passes_through_when_disabled() {
+        let config = CompressionConfig::default();
+
+        assert!(make_compression_layer(&config).is_none());
+
+        let response = respond(&config, "gzip, identity;q=0", "text/html", 64).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert!(!response.headers().contains_key(VARY));
+    }
+}
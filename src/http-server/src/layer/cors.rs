@@ -0,0 +1,405 @@
+//! Cross-Origin Resource Sharing as described in the [Fetch Standard][1].
+//!
+//! [1]: https://fetch.spec.whatwg.org/#http-cors-protocol
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Error, Result, bail};
+use http::{HeaderName, HeaderValue, Method};
+use regex::Regex;
+use serde::Deserialize;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+
+use crate::config::CorsConfig;
+use crate::handler::virtual_host::HostPattern;
+
+/// Methods allowed when not configured.
+const DEFAULT_METHODS: [Method; 2] = [Method::GET, Method::POST];
+
+/// An origin allowed to perform cross-origin requests, such as
+/// `https://dashboard.example.com`, `https://*.example.com:8443` or
+/// `http://[::1]:8080`.
+///
+/// The host is matched as a `HostPattern`, so a leading `*.` matches any
+/// subdomain. The `*` pattern matches every origin. Patterns starting with
+/// `regex:` are regular expressions matched against the whole origin, such
+/// as `regex:https://(dev|staging)-[0-9]+\.example\.com`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum OriginPattern {
+    Any,
+    Origin {
+        scheme: String,
+        host: HostPattern,
+        port: Option<u16>,
+    },
+    Regex(Regex),
+}
+
+impl OriginPattern {
+    /// Checks whether the value of an `Origin` header matches this pattern.
+    pub fn matches(&self, origin: &str) -> bool {
+        let (scheme, host, port) = match self {
+            OriginPattern::Any => return true,
+            OriginPattern::Origin { scheme, host, port } => (scheme, host, port),
+            OriginPattern::Regex(regex) => return regex.is_match(origin),
+        };
+        let Some((origin_scheme, authority)) = origin.split_once("://") else {
+            return false;
+        };
+        let Some((origin_host, origin_port)) = split_authority(authority) else {
+            return false;
+        };
+
+        origin_scheme.eq_ignore_ascii_case(scheme)
+            && origin_port == *port
+            && host.matches(origin_host)
+    }
+}
+
+/// Splits the authority of an origin into its host and port, removing the
+/// brackets enclosing IPv6 addresses. `None` when the port is invalid.
+fn split_authority(authority: &str) -> Option<(&str, Option<u16>)> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']')?;
+
+            match rest {
+                "" => (host, None),
+                _ => (host, Some(rest.strip_prefix(':')?)),
+            }
+        }
+        None => match authority.split_once(':') {
+            // IPv6 addresses must be enclosed in brackets
+            Some((_, port)) if port.contains(':') => return None,
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+
+    match port {
+        Some(port) => Some((host, Some(port.parse::<u16>().ok()?))),
+        None => Some((host, None)),
+    }
+}
+
+impl FromStr for OriginPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(regex) = s.trim().strip_prefix("regex:") {
+            // Anchored so a pattern cannot match part of another origin
+            let regex = Regex::new(&format!("^(?:{regex})$"))
+                .with_context(|| format!("Invalid origin regular expression \"{regex}\"."))?;
+
+            return Ok(OriginPattern::Regex(regex));
+        }
+
+        let pattern = s.trim().trim_end_matches('/');
+
+        if pattern == "*" {
+            return Ok(OriginPattern::Any);
+        }
+
+        let invalid = || {
+            format!("Invalid origin \"{s}\", expected an origin such as \"https://example.com\".")
+        };
+        let (scheme, authority) = pattern.split_once("://").with_context(invalid)?;
+        let scheme = scheme.to_ascii_lowercase();
+
+        if scheme != "http" && scheme != "https" {
+            bail!(invalid());
+        }
+
+        let (host, port) = split_authority(authority).with_context(invalid)?;
+
+        Ok(OriginPattern::Origin {
+            scheme,
+            host: HostPattern::from_str(host).with_context(invalid)?,
+            port,
+        })
+    }
+}
+
+impl TryFrom<String> for OriginPattern {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::from_str(&value)
+    }
+}
+
+/// Creates the `CorsLayer` for the provided settings, `None` when CORS is
+/// disabled.
+pub fn make_cors_layer(config: &CorsConfig) -> Result<Option<CorsLayer>> {
+    if !config.is_enabled() {
+        return Ok(None);
+    }
+
+    let credentials = config.allow_credentials.unwrap_or(false);
+    let origins = config.allow_origins.clone().unwrap_or_default();
+    let any_origin = origins.is_empty()
+        || origins
+            .iter()
+            .any(|origin| matches!(origin, OriginPattern::Any));
+
+    if credentials && (any_origin || is_wildcard(config)) {
+        bail!("CORS credentials cannot be allowed along with a wildcard (\"*\").");
+    }
+
+    let allow_origin = if any_origin {
+        AllowOrigin::any()
+    } else {
+        let origins = Arc::new(origins);
+
+        AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| origins.iter().any(|pattern| pattern.matches(origin)))
+        })
+    };
+    let allow_methods = match &config.allow_methods {
+        Some(methods) if is_any(methods) => AllowMethods::any(),
+        Some(methods) => AllowMethods::list(
+            methods
+                .iter()
+                .map(|method| {
+                    Method::from_str(&method.to_ascii_uppercase())
+                        .with_context(|| format!("Invalid CORS method \"{method}\"."))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        None => AllowMethods::list(DEFAULT_METHODS),
+    };
+    let allow_headers = match &config.allow_headers {
+        Some(headers) if is_any(headers) => AllowHeaders::any(),
+        Some(headers) => AllowHeaders::list(header_names(headers)?),
+        None => AllowHeaders::list([]),
+    };
+    let expose_headers = match &config.expose_headers {
+        Some(headers) if is_any(headers) => ExposeHeaders::any(),
+        Some(headers) => ExposeHeaders::list(header_names(headers)?),
+        None => ExposeHeaders::list([]),
+    };
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers)
+        .expose_headers(expose_headers)
+        .allow_credentials(credentials);
+
+    if let Some(max_age) = config.max_age {
+        layer = layer.max_age(Duration::from_secs(max_age));
+    }
+
+    Ok(Some(layer))
+}
+
+fn is_any(values: &[String]) -> bool {
+    values.iter().any(|value| value == "*")
+}
+
+/// Checks whether any list of methods or headers allows any value.
+fn is_wildcard(config: &CorsConfig) -> bool {
+    [
+        &config.allow_methods,
+        &config.allow_headers,
+        &config.expose_headers,
+    ]
+    .into_iter()
+    .flatten()
+    .any(|values| is_any(values))
+}
+
+fn header_names(headers: &[String]) -> Result<Vec<HeaderName>> {
+    headers
+        .iter()
+        .map(|header| {
+            HeaderName::from_str(header)
+                .with_context(|| format!("Invalid CORS header \"{header}\"."))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use anyhow::Error;
+    use http::header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_METHODS,
+        ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+    };
+    use http::{Method, Request, Response};
+    use tower::{ServiceBuilder, ServiceExt, service_fn};
+
+    use super::{OriginPattern, make_cors_layer};
+    use crate::config::CorsConfig;
+    use crate::server::{HttpResponse, full_body};
+
+    fn origins(origins: &[&str]) -> Option<Vec<OriginPattern>> {
+        Some(
+            origins
+                .iter()
+                .map(|origin| OriginPattern::from_str(origin).unwrap())
+                .collect(),
+        )
+    }
+
+    async fn respond(config: &CorsConfig, request: Request<()>) -> HttpResponse {
+        let service = ServiceBuilder::new()
+            .option_layer(make_cors_layer(config).unwrap())
+            .service(service_fn(|_: Request<()>| async {
+                Ok::<HttpResponse, Error>(Response::new(full_body("ok")))
+            }));
+
+        service.oneshot(request).await.unwrap()
+    }
+
+    fn preflight(origin: &str) -> Request<()> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_origin_patterns() {
+        let exact = OriginPattern::from_str("https://Dashboard.example.com/").unwrap();
+        let wildcard = OriginPattern::from_str("https://*.example.com:8443").unwrap();
+
+        assert!(exact.matches("https://dashboard.example.com"));
+        assert!(!exact.matches("http://dashboard.example.com"));
+        assert!(!exact.matches("https://dashboard.example.com:8443"));
+        assert!(!exact.matches("null"));
+        assert!(wildcard.matches("https://a.example.com:8443"));
+        assert!(!wildcard.matches("https://a.example.com"));
+        assert!(!wildcard.matches("https://example.com:8443"));
+        assert!(OriginPattern::from_str("*").unwrap().matches("null"));
+        assert!(OriginPattern::from_str("example.com").is_err());
+        assert!(OriginPattern::from_str("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn matches_ipv6_origins() {
+        let loopback = OriginPattern::from_str("http://[::1]:8080").unwrap();
+
+        assert!(loopback.matches("http://[::1]:8080"));
+        assert!(loopback.matches("http://[0:0::1]:8080"));
+        assert!(!loopback.matches("http://[::1]"));
+        assert!(!loopback.matches("http://[::2]:8080"));
+        assert!(
+            OriginPattern::from_str("http://[fe80::1]")
+                .unwrap()
+                .matches("http://[FE80::1]")
+        );
+        assert!(OriginPattern::from_str("http://[::1").is_err());
+        assert!(OriginPattern::from_str("http://[::1]8080").is_err());
+        assert!(OriginPattern::from_str("http://::1").is_err());
+        assert!(OriginPattern::from_str("http://::1:8080").is_err());
+    }
+
+    #[test]
+    fn matches_regex_origins() {
+        let regex =
+            OriginPattern::from_str(r"regex:https://(dev|staging)-[0-9]+\.example\.com").unwrap();
+
+        assert!(regex.matches("https://dev-1.example.com"));
+        assert!(regex.matches("https://staging-42.example.com"));
+        assert!(!regex.matches("https://prod-1.example.com"));
+        assert!(!regex.matches("https://dev-1.example.com.evil.com"));
+        assert!(!regex.matches("http://dev-1.example.com"));
+        assert!(OriginPattern::from_str("regex:https://(dev").is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_preflight_requests_from_allowed_origins() {
+        let config = CorsConfig {
+            allow_origins: origins(&["https://dashboard.example.com"]),
+            allow_credentials: Some(true),
+            max_age: Some(600),
+            ..CorsConfig::default()
+        };
+        let response = respond(&config, preflight("https://dashboard.example.com")).await;
+        let headers = response.headers();
+
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://dashboard.example.com"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET,POST");
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+
+        let response = respond(&config, preflight("https://evil.example.com")).await;
+
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn allows_any_origin_by_default() {
+        let config = CorsConfig {
+            enabled: Some(true),
+            ..CorsConfig::default()
+        };
+        let request = Request::builder()
+            .header(ORIGIN, "https://example.com")
+            .body(())
+            .unwrap();
+        let response = respond(&config, request).await;
+
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+
+    #[test]
+    fn rejects_credentials_with_wildcards() {
+        let config = CorsConfig {
+            allow_credentials: Some(true),
+            ..CorsConfig::default()
+        };
+
+        assert!(
+            make_cors_layer(&CorsConfig {
+                allow_origins: origins(&["*"]),
+                ..config.clone()
+            })
+            .is_err()
+        );
+        assert!(
+            make_cors_layer(&CorsConfig {
+                allow_origins: origins(&["https://example.com"]),
+                allow_headers: Some(vec![String::from("*")]),
+                ..config
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_methods_and_headers() {
+        let config = CorsConfig {
+            enabled: Some(true),
+            ..CorsConfig::default()
+        };
+
+        assert!(
+            make_cors_layer(&CorsConfig {
+                allow_methods: Some(vec![String::from("GET POST")]),
+                ..config.clone()
+            })
+            .is_err()
+        );
+        assert!(
+            make_cors_layer(&CorsConfig {
+                expose_headers: Some(vec![String::from("x header")]),
+                ..config
+            })
+            .is_err()
+        );
+        assert!(make_cors_layer(&CorsConfig::default()).unwrap().is_none());
+    }
+}
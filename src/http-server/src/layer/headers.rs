@@ -0,0 +1,332 @@
+//! Headers added to the responses of the inner service.
+//!
+//! `ResponseHeadersLayer` adds static headers to every response, replacing
+//! any header with the same name set by the inner service, whereas
+//! `HeaderRulesLayer` modifies the headers of the responses matching each
+//! `HeaderRule`.
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use globset::GlobMatcher;
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, HeaderName, Request, StatusCode};
+use percent_encoding::percent_decode_str;
+use tower::{Layer, Service};
+
+use crate::server::HttpResponse;
+
+#[derive(Clone)]
+pub struct ResponseHeadersLayer {
+    headers: Arc<HeaderMap>,
+}
+
+impl ResponseHeadersLayer {
+    pub fn new(headers: HeaderMap) -> Self {
+        Self {
+            headers: Arc::new(headers),
+        }
+    }
+}
+
+impl<S> Layer<S> for ResponseHeadersLayer {
+    type Service = ResponseHeadersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ResponseHeadersService {
+            inner,
+            headers: Arc::clone(&self.headers),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseHeadersService<S> {
+    inner: S,
+    headers: Arc<HeaderMap>,
+}
+
+impl<S, B> Service<Request<B>> for ResponseHeadersService<S>
+where
+    S: Service<Request<B>, Response = HttpResponse>,
+    S::Future: Send + 'static,
+{
+    type Response = HttpResponse;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<HttpResponse, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let headers = Arc::clone(&self.headers);
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut response = future.await?;
+
+            for (name, value) in headers.iter() {
+                response.headers_mut().insert(name, value.clone());
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+/// Modifications to the headers of responses matching every provided
+/// condition. Headers are removed first, then set and finally appended.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderRule {
+    /// Pattern matched against the request path, such as `/app/**` or
+    /// `*.wasm`
+    pub path: Option<GlobMatcher>,
+    /// Status codes of matching responses
+    pub status: Vec<StatusCode>,
+    /// MIME types of matching responses, such as `text/html` or `image/*`
+    pub mime: Vec<String>,
+    /// Headers replacing any header with the same name
+    pub set: HeaderMap,
+    /// Headers added along with any header with the same name
+    pub append: HeaderMap,
+    /// Headers removed from responses
+    pub remove: Vec<HeaderName>,
+}
+
+impl HeaderRule {
+    /// Checks whether the rule applies to the response of the request for
+    /// `path`. The MIME type of the response is its `Content-Type` without
+    /// parameters.
+    pub fn matches(&self, path: &str, status: StatusCode, mime: Option<&str>) -> bool {
+        self.path.as_ref().is_none_or(|glob| glob.is_match(path))
+            && (self.status.is_empty() || self.status.contains(&status))
+            && (self.mime.is_empty()
+                || mime.is_some_and(|mime| {
+                    self.mime
+                        .iter()
+                        .any(|pattern| match pattern.strip_suffix("/*") {
+                            Some(kind) => mime
+                                .split_once('/')
+                                .is_some_and(|(mime_kind, _)| mime_kind.eq_ignore_ascii_case(kind)),
+                            None => mime.eq_ignore_ascii_case(pattern),
+                        })
+                }))
+    }
+}
+
+#[derive(Clone)]
+pub struct HeaderRulesLayer {
+    rules: Arc<[HeaderRule]>,
+}
+
+impl HeaderRulesLayer {
+    pub fn new(rules: Vec<HeaderRule>) -> Self {
+        Self {
+            rules: rules.into(),
+        }
+    }
+}
+
+impl<S> Layer<S> for HeaderRulesLayer {
+    type Service = HeaderRulesService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HeaderRulesService {
+            inner,
+            rules: Arc::clone(&self.rules),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HeaderRulesService<S> {
+    inner: S,
+    rules: Arc<[HeaderRule]>,
+}
+
+impl<S, B> Service<Request<B>> for HeaderRulesService<S>
+where
+    S: Service<Request<B>, Response = HttpResponse>,
+    S::Future: Send + 'static,
+{
+    type Response = HttpResponse;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<HttpResponse, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let rules = Arc::clone(&self.rules);
+        let path = percent_decode_str(req.uri().path())
+            .decode_utf8_lossy()
+            .into_owned();
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut response = future.await?;
+            let status = response.status();
+            let mime = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| {
+                    value
+                        .split(';')
+                        .next()
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string()
+                });
+
+            for rule in rules
+                .iter()
+                .filter(|rule| rule.matches(&path, status, mime.as_deref()))
+            {
+                let headers = response.headers_mut();
+
+                for name in &rule.remove {
+                    headers.remove(name);
+                }
+
+                for (name, value) in rule.set.iter() {
+                    headers.insert(name, value.clone());
+                }
+
+                for (name, value) in rule.append.iter() {
+                    headers.append(name, value.clone());
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use globset::Glob;
+    use http::header::{CONTENT_TYPE, ETAG, LINK, X_FRAME_OPTIONS};
+    use http::{HeaderMap, HeaderValue, Request, Response, StatusCode};
+    use tower::{Layer, ServiceExt, service_fn};
+
+    use super::{HeaderRule, HeaderRulesLayer, ResponseHeadersLayer};
+    use crate::server::{HttpResponse, full_body};
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    fn response(status: StatusCode) -> HttpResponse {
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(ETAG, "\"abc\"")
+            .header(LINK, "</style.css>; rel=preload")
+            .header(X_FRAME_OPTIONS, "SAMEORIGIN")
+            .body(full_body("ok"))
+            .unwrap()
+    }
+
+    async fn apply_rules(rules: Vec<HeaderRule>, uri: &str, status: StatusCode) -> HeaderMap {
+        let service =
+            HeaderRulesLayer::new(rules).layer(service_fn(move |_: Request<()>| async move {
+                Ok::<HttpResponse, Error>(response(status))
+            }));
+        let request = Request::builder().uri(uri).body(()).unwrap();
+
+        service.oneshot(request).await.unwrap().headers().clone()
+    }
+
+    #[test]
+    fn matches_rules() {
+        let rule = HeaderRule {
+            path: Some(Glob::new("/app/**").unwrap().compile_matcher()),
+            mime: vec![String::from("text/html"), String::from("image/*")],
+            ..HeaderRule::default()
+        };
+
+        assert!(rule.matches("/app/index.html", StatusCode::OK, Some("text/html")));
+        assert!(rule.matches("/app/logo.png", StatusCode::OK, Some("IMAGE/png")));
+        assert!(!rule.matches("/app/app.wasm", StatusCode::OK, Some("application/wasm")));
+        assert!(!rule.matches("/app/index", StatusCode::OK, None));
+        assert!(!rule.matches("/index.html", StatusCode::OK, Some("text/html")));
+
+        let rule = HeaderRule {
+            status: vec![StatusCode::NOT_FOUND],
+            ..HeaderRule::default()
+        };
+
+        assert!(rule.matches("/missing", StatusCode::NOT_FOUND, None));
+        assert!(!rule.matches("/index.html", StatusCode::OK, Some("text/html")));
+        assert!(HeaderRule::default().matches("/", StatusCode::OK, None));
+    }
+
+    #[tokio::test]
+    async fn applies_matching_rules() {
+        let rules = vec![
+            HeaderRule {
+                path: Some(Glob::new("/my app/**").unwrap().compile_matcher()),
+                mime: vec![String::from("text/html")],
+                set: headers(&[("x-frame-options", "DENY")]),
+                append: headers(&[("link", "</app.wasm>; rel=preload")]),
+                ..HeaderRule::default()
+            },
+            HeaderRule {
+                status: vec![StatusCode::NOT_FOUND],
+                remove: vec![ETAG],
+                ..HeaderRule::default()
+            },
+        ];
+        let headers = apply_rules(rules.clone(), "/my%20app/index.html", StatusCode::OK).await;
+
+        assert_eq!(headers[X_FRAME_OPTIONS], "DENY");
+        assert_eq!(headers.get_all(LINK).iter().count(), 2);
+        assert!(headers.contains_key(ETAG));
+
+        let headers = apply_rules(rules, "/index.html", StatusCode::NOT_FOUND).await;
+
+        assert_eq!(headers[X_FRAME_OPTIONS], "SAMEORIGIN");
+        assert_eq!(headers.get_all(LINK).iter().count(), 1);
+        assert!(!headers.contains_key(ETAG));
+    }
+
+    #[tokio::test]
+    async fn removes_headers_before_setting_them() {
+        let rules = vec![HeaderRule {
+            remove: vec![LINK],
+            append: headers(&[("link", "</app.js>; rel=preload")]),
+            ..HeaderRule::default()
+        }];
+        let headers = apply_rules(rules, "/", StatusCode::OK).await;
+
+        assert_eq!(
+            headers.get_all(LINK).iter().collect::<Vec<_>>(),
+            ["</app.js>; rel=preload"]
+        );
+    }
+
+    #[tokio::test]
+    async fn replaces_response_headers() {
+        let layer = ResponseHeadersLayer::new(headers(&[("x-frame-options", "DENY")]));
+        let service = layer.layer(service_fn(|_: Request<()>| async {
+            Ok::<HttpResponse, Error>(response(StatusCode::OK))
+        }));
+        let response = service.oneshot(Request::new(())).await.unwrap();
+
+        assert_eq!(
+            response
+                .headers()
+                .get_all(X_FRAME_OPTIONS)
+                .iter()
+                .collect::<Vec<_>>(),
+            ["DENY"]
+        );
+    }
+}
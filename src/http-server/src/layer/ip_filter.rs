@@ -0,0 +1,365 @@
+//! Rejects requests from client addresses not allowed by an `IpFilter` with
+//! `403 Forbidden`, without reaching the inner service.
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{Context as _, Error, Result};
+use futures::future::{Either, Ready, ready};
+use http::{HeaderMap, HeaderName, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use tower::{Layer, Service};
+
+use crate::server::{ClientIp, HttpResponse, full_body};
+
+/// Header through which trusted proxies provide the client address.
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// An IPv4 or IPv6 network in CIDR notation, such as `10.0.0.0/8` or
+/// `fd00::/8`. A single address, such as `192.168.1.10`, matches only such
+/// address.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Checks whether `ip` belongs to this network. IPv4-mapped IPv6
+    /// addresses are compared as IPv4 addresses.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = IpAddr::from_str(addr)
+            .with_context(|| format!("Invalid IP network \"{s}\"."))?
+            .to_canonical();
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .with_context(|| format!("Invalid prefix length in IP network \"{s}\"."))?,
+            None => max_prefix,
+        };
+
+        Ok(IpNetwork { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        Self::from_str(&value)
+    }
+}
+
+/// Client addresses allowed to access the server or a route.
+///
+/// Addresses in `deny` are always rejected. When `allow` is not empty, only
+/// addresses in `allow` are accepted.
+///
+/// Route filters can be limited to some request `methods`, so uploads to the
+/// file explorer can be restricted while anyone can browse it. The global
+/// filter, also checked when connections are accepted, applies to every
+/// method.
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    pub allow: Vec<IpNetwork>,
+    pub deny: Vec<IpNetwork>,
+    /// Request methods the filter applies to, every method when empty
+    pub methods: Vec<Method>,
+}
+
+impl IpFilter {
+    /// Checks whether requests with `method` are subject to the filter.
+    pub fn applies_to(&self, method: &Method) -> bool {
+        self.methods.is_empty() || self.methods.contains(method)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|network| network.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip)))
+    }
+}
+
+/// Resolves the address of the client which sent a request received from
+/// `remote`.
+///
+/// When `remote` is a trusted proxy, the `X-Forwarded-For` header is read
+/// from right to left skipping trusted proxies, so clients cannot spoof
+/// their address by sending the header themselves.
+pub fn client_ip(remote: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNetwork]) -> IpAddr {
+    if !trusted_proxies
+        .iter()
+        .any(|network| network.contains(remote))
+    {
+        return remote;
+    }
+
+    forwarded_client_ip(remote, headers, trusted_proxies)
+}
+
+/// Resolves the address of the client which sent a request forwarded by
+/// `remote`, a proxy trusted whatever its address, such as any peer of a Unix
+/// domain socket.
+pub fn forwarded_client_ip(
+    remote: IpAddr,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpNetwork],
+) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|network| network.contains(ip));
+    let forwarded = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    let mut client = remote;
+
+    for value in forwarded.into_iter().rev() {
+        let value = value.trim();
+        let Some(ip) = IpAddr::from_str(value)
+            .ok()
+            .or_else(|| SocketAddr::from_str(value).ok().map(|addr| addr.ip()))
+        else {
+            break;
+        };
+
+        client = ip;
+
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+
+    client
+}
+
+#[derive(Clone)]
+pub struct IpFilterLayer {
+    filter: Arc<IpFilter>,
+}
+
+impl IpFilterLayer {
+    pub fn new(filter: IpFilter) -> Self {
+        Self {
+            filter: Arc::new(filter),
+        }
+    }
+}
+
+impl<S> Layer<S> for IpFilterLayer {
+    type Service = IpFilterService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpFilterService {
+            inner,
+            filter: Arc::clone(&self.filter),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IpFilterService<S> {
+    inner: S,
+    filter: Arc<IpFilter>,
+}
+
+impl<S, B> Service<Request<B>> for IpFilterService<S>
+where
+    S: Service<Request<B>, Response = HttpResponse>,
+{
+    type Response = HttpResponse;
+    type Error = S::Error;
+    type Future = Either<S::Future, Ready<Result<HttpResponse, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Requests missing the client address are rejected, as it cannot be
+        // checked against the filter
+        let allowed = !self.filter.applies_to(req.method())
+            || req
+                .extensions()
+                .get::<ClientIp>()
+                .is_some_and(|ClientIp(ip)| self.filter.is_allowed(*ip));
+
+        if allowed {
+            return Either::Left(self.inner.call(req));
+        }
+
+        let response = Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(full_body("Forbidden"))
+            .expect("Failed to build Forbidden response");
+
+        Either::Right(ready(Ok(response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    use anyhow::Error;
+    use http::{HeaderMap, Method, Request, Response, StatusCode};
+    use tower::{Layer, ServiceExt, service_fn};
+
+    use super::{IpFilter, IpFilterLayer, IpNetwork, client_ip, forwarded_client_ip};
+    use crate::server::{ClientIp, HttpResponse, full_body};
+
+    #[test]
+    fn matches_ip_networks() {
+        let network = |s: &str| IpNetwork::from_str(s).unwrap();
+        let ip = |s: &str| IpAddr::from_str(s).unwrap();
+
+        assert!(network("10.0.0.0/8").contains(ip("10.1.2.3")));
+        assert!(!network("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(network("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(network("0.0.0.0/0").contains(ip("1.2.3.4")));
+        assert!(!network("0.0.0.0/0").contains(ip("::1")));
+        assert!(network("fd00::/8").contains(ip("fd12::1")));
+        assert!(network("192.168.1.10").contains(ip("192.168.1.10")));
+        assert!(!network("192.168.1.10").contains(ip("192.168.1.11")));
+        assert!(IpNetwork::from_str("10.0.0.0/33").is_err());
+        assert!(IpNetwork::from_str("10.0.0/8").is_err());
+
+        let filter = IpFilter {
+            allow: vec![network("10.0.0.0/8")],
+            deny: vec![network("10.0.0.5")],
+            methods: Vec::new(),
+        };
+
+        assert!(filter.is_allowed(ip("10.0.0.4")));
+        assert!(!filter.is_allowed(ip("10.0.0.5")));
+        assert!(!filter.is_allowed(ip("192.168.0.1")));
+    }
+
+    #[test]
+    fn resolves_client_ip() {
+        let trusted = [IpNetwork::from_str("10.0.0.0/8").unwrap()];
+        let resolve = |remote: &str, forwarded: &str| {
+            let mut headers = HeaderMap::new();
+
+            headers.insert("x-forwarded-for", forwarded.parse().unwrap());
+            client_ip(remote.parse().unwrap(), &headers, &trusted).to_string()
+        };
+
+        assert_eq!(resolve("192.0.2.1", "198.51.100.1"), "192.0.2.1");
+        assert_eq!(resolve("10.0.0.1", "198.51.100.1"), "198.51.100.1");
+        assert_eq!(
+            resolve("10.0.0.1", "203.0.113.9, 198.51.100.1, 10.0.0.2"),
+            "198.51.100.1"
+        );
+        assert_eq!(resolve("10.0.0.1", "garbage, 10.0.0.2"), "10.0.0.2");
+
+        let mut headers = HeaderMap::new();
+        let remote = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(forwarded_client_ip(remote, &headers, &[]), remote);
+
+        headers.insert(
+            "x-forwarded-for",
+            "203.0.113.9, 198.51.100.1".parse().unwrap(),
+        );
+
+        assert_eq!(
+            forwarded_client_ip(remote, &headers, &[]).to_string(),
+            "198.51.100.1"
+        );
+    }
+
+    async fn respond(filter: &IpFilter, method: Method, client: Option<&str>) -> StatusCode {
+        let service =
+            IpFilterLayer::new(filter.clone()).layer(service_fn(|_: Request<()>| async {
+                Ok::<HttpResponse, Error>(Response::new(full_body("ok")))
+            }));
+        let mut request = Request::builder().method(method).body(()).unwrap();
+
+        if let Some(client) = client {
+            request
+                .extensions_mut()
+                .insert(ClientIp(client.parse().unwrap()));
+        }
+
+        service.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn forbids_filtered_clients() {
+        let filter = IpFilter {
+            allow: vec![IpNetwork::from_str("192.0.2.0/24").unwrap()],
+            deny: Vec::new(),
+            methods: Vec::new(),
+        };
+
+        assert_eq!(
+            respond(&filter, Method::GET, Some("192.0.2.7")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            respond(&filter, Method::GET, Some("198.51.100.1")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            respond(&filter, Method::GET, None).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_uploads_only() {
+        // Uploads only from 10.0.0.0/8, browsing from anywhere
+        let filter = IpFilter {
+            allow: vec![IpNetwork::from_str("10.0.0.0/8").unwrap()],
+            deny: Vec::new(),
+            methods: vec![Method::POST],
+        };
+
+        assert_eq!(
+            respond(&filter, Method::GET, Some("192.0.2.7")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            respond(&filter, Method::POST, Some("192.0.2.7")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            respond(&filter, Method::POST, Some("10.1.2.3")).await,
+            StatusCode::OK
+        );
+    }
+}
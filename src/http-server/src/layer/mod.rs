@@ -0,0 +1,8 @@
+pub mod access_log;
+pub mod basic_auth;
+pub mod compression;
+pub mod cors;
+pub mod headers;
+pub mod ip_filter;
+pub mod rate_limit;
+pub mod secure_headers;
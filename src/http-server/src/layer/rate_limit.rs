@@ -0,0 +1,528 @@
+//! Limits the requests, response bytes and concurrent file transfers of each
+//! client using token buckets.
+//!
+//! Requests above the request rate or the transfer caps are answered with
+//! `429 Too Many Requests` and a `Retry-After` header. Response bodies
+//! exceeding the byte rate are slowed down instead. Only responses streaming
+//! a file, marked with `FileTransfer`, count as transfers, which last until
+//! their body has been sent or dropped.
+//!
+//! Clients are identified by their IP address, or by their `/64` network for
+//! IPv6 clients, as a single host usually holds a whole `/64` network.
+use std::collections::HashMap;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use bytes::{Buf, Bytes};
+use futures::future::{BoxFuture, Either, Ready, ready};
+use http::header::RETRY_AFTER;
+use http::{Request, Response, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, SizeHint};
+use tokio::time::{Sleep, sleep};
+use tower::{Layer, Service};
+
+use crate::config::RateLimitConfig;
+use crate::handler::range::FileTransfer;
+use crate::server::{ClientIp, HttpBody, HttpResponse, full_body};
+
+/// Number of clients tracked at most, for each kind of bucket.
+const MAX_CLIENTS: usize = 4096;
+
+/// Seconds clients are asked to wait when a transfer cap is reached.
+const TRANSFER_RETRY_AFTER: u64 = 1;
+
+/// Tokens refilled at a constant rate up to a capacity.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, capacity: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.updated = now;
+    }
+}
+
+/// Buckets of the clients seen recently, holding at most `MAX_CLIENTS`.
+///
+/// Buckets are kept in two generations. Once the current generation is full
+/// it replaces the previous one, so clients not seen for a whole generation
+/// are forgotten without scanning every bucket.
+#[derive(Default)]
+struct Buckets {
+    current: HashMap<IpAddr, Bucket>,
+    previous: HashMap<IpAddr, Bucket>,
+}
+
+impl Buckets {
+    /// Retrieves the bucket of `client`, created full with `capacity` tokens
+    /// for clients not seen recently.
+    fn get(&mut self, client: IpAddr, capacity: f64, now: Instant) -> &mut Bucket {
+        if !self.current.contains_key(&client) {
+            let bucket = self
+                .previous
+                .remove(&client)
+                .unwrap_or_else(|| Bucket::new(capacity, now));
+
+            if self.current.len() >= MAX_CLIENTS / 2 {
+                self.previous = mem::take(&mut self.current);
+            }
+
+            self.current.insert(client, bucket);
+        }
+
+        self.current
+            .get_mut(&client)
+            .expect("Bucket inserted for client")
+    }
+}
+
+#[derive(Default)]
+struct State {
+    requests: Buckets,
+    bytes: Buckets,
+    transfers: usize,
+    transfers_per_client: HashMap<IpAddr, usize>,
+}
+
+/// Limits shared by every connection, holding the buckets of each client.
+struct RateLimiter {
+    config: RateLimitConfig,
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn request_rate(&self) -> Option<(f64, f64)> {
+        self.config.requests_per_second.map(|rate| {
+            let burst = self.config.burst.unwrap_or(rate);
+
+            (rate.get() as f64, burst.get() as f64)
+        })
+    }
+
+    fn byte_rate(&self) -> Option<f64> {
+        self.config.bytes_per_second.map(|rate| rate.get() as f64)
+    }
+
+    fn has_transfer_caps(&self) -> bool {
+        self.config.max_transfers.is_some() || self.config.max_transfers_per_ip.is_some()
+    }
+
+    /// Takes a request token for `client`, or returns the seconds to wait
+    /// before retrying.
+    fn take_request(&self, client: IpAddr, now: Instant) -> Result<(), u64> {
+        let Some((rate, burst)) = self.request_rate() else {
+            return Ok(());
+        };
+        let mut state = self.state.lock().expect("Rate limiter lock poisoned");
+        let bucket = state.requests.get(client, burst, now);
+
+        bucket.refill(rate, burst, now);
+
+        if bucket.tokens < 1. {
+            return Err(((1. - bucket.tokens) / rate).ceil().max(1.) as u64);
+        }
+
+        bucket.tokens -= 1.;
+
+        Ok(())
+    }
+
+    /// Takes a transfer slot for `client`, or returns the seconds to wait
+    /// before retrying once a transfer cap is reached.
+    fn start_transfer(self: &Arc<Self>, client: IpAddr) -> Result<Transfer, u64> {
+        let mut state = self.state.lock().expect("Rate limiter lock poisoned");
+
+        if let Some(max) = self.config.max_transfers
+            && state.transfers >= max.get()
+        {
+            return Err(TRANSFER_RETRY_AFTER);
+        }
+
+        if let Some(max) = self.config.max_transfers_per_ip
+            && state
+                .transfers_per_client
+                .get(&client)
+                .copied()
+                .unwrap_or(0)
+                >= max.get()
+        {
+            return Err(TRANSFER_RETRY_AFTER);
+        }
+
+        state.transfers += 1;
+        *state.transfers_per_client.entry(client).or_default() += 1;
+
+        Ok(Transfer {
+            limiter: Arc::clone(self),
+            client,
+        })
+    }
+
+    /// Takes `len` byte tokens for `client`, returning how long to wait
+    /// before sending more bytes once the bucket runs into debt.
+    fn consume(&self, client: IpAddr, len: usize, now: Instant) -> Option<Duration> {
+        let rate = self.byte_rate()?;
+        let mut state = self.state.lock().expect("Rate limiter lock poisoned");
+        let bucket = state.bytes.get(client, rate, now);
+
+        bucket.refill(rate, rate, now);
+        bucket.tokens -= len as f64;
+
+        (bucket.tokens < 0.).then(|| Duration::from_secs_f64(-bucket.tokens / rate))
+    }
+
+    fn release(&self, client: IpAddr) {
+        let mut state = self.state.lock().expect("Rate limiter lock poisoned");
+
+        state.transfers -= 1;
+
+        if let Some(count) = state.transfers_per_client.get_mut(&client) {
+            *count -= 1;
+
+            if *count == 0 {
+                state.transfers_per_client.remove(&client);
+            }
+        }
+    }
+}
+
+/// Transfer slot of a client, released when dropped.
+struct Transfer {
+    limiter: Arc<RateLimiter>,
+    client: IpAddr,
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        self.limiter.release(self.client);
+    }
+}
+
+/// Identifies the client at `ip`, by its `/64` network for IPv6 addresses.
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & (u128::MAX << 64))),
+        ip => ip,
+    }
+}
+
+fn too_many_requests(retry_after: u64) -> HttpResponse {
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(RETRY_AFTER, retry_after)
+        .body(full_body("Too Many Requests"))
+        .expect("Failed to build Too Many Requests response")
+}
+
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            limiter: Arc::new(RateLimiter::new(config)),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: Arc::clone(&self.limiter),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S, B> Service<Request<B>> for RateLimitService<S>
+where
+    S: Service<Request<B>, Response = HttpResponse>,
+    S::Future: Send + 'static,
+{
+    type Response = HttpResponse;
+    type Error = S::Error;
+    type Future = Either<
+        BoxFuture<'static, Result<HttpResponse, S::Error>>,
+        Ready<Result<HttpResponse, S::Error>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Requests missing the client address share a single bucket
+        let client = req
+            .extensions()
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| client_key(*ip))
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+        if let Err(retry_after) = self.limiter.take_request(client, Instant::now()) {
+            return Either::Right(ready(Ok(too_many_requests(retry_after))));
+        }
+
+        let limiter = Arc::clone(&self.limiter);
+        let future = self.inner.call(req);
+
+        Either::Left(Box::pin(async move {
+            let response = future.await?;
+            let transfer = if limiter.has_transfer_caps()
+                && response.extensions().get::<FileTransfer>().is_some()
+            {
+                match limiter.start_transfer(client) {
+                    Ok(transfer) => Some(transfer),
+                    Err(retry_after) => return Ok(too_many_requests(retry_after)),
+                }
+            } else {
+                None
+            };
+
+            if transfer.is_none() && limiter.byte_rate().is_none() {
+                return Ok(response);
+            }
+
+            Ok(response.map(|inner| {
+                RateLimitBody {
+                    inner,
+                    limiter,
+                    client,
+                    _transfer: transfer,
+                    delay: None,
+                }
+                .boxed()
+            }))
+        }))
+    }
+}
+
+/// Response body holding the transfer slot of the client, if any, delaying
+/// frames once the client exceeds its byte rate.
+struct RateLimitBody {
+    inner: HttpBody,
+    limiter: Arc<RateLimiter>,
+    client: IpAddr,
+    _transfer: Option<Transfer>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Body for RateLimitBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if let Some(delay) = &mut this.delay {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            this.delay = None;
+        }
+
+        let frame = Pin::new(&mut this.inner).poll_frame(cx);
+
+        if let Poll::Ready(Some(Ok(frame))) = &frame
+            && let Some(data) = frame.data_ref()
+            && let Some(delay) = this
+                .limiter
+                .consume(this.client, data.remaining(), Instant::now())
+        {
+            this.delay = Some(Box::pin(sleep(delay)));
+        }
+
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use anyhow::Error;
+    use http::header::RETRY_AFTER;
+    use http::{Request, Response, StatusCode};
+    use tower::{Layer, ServiceExt, service_fn};
+
+    use super::{Buckets, MAX_CLIENTS, RateLimitLayer, RateLimiter, client_key};
+    use crate::config::RateLimitConfig;
+    use crate::handler::range::FileTransfer;
+    use crate::server::{ClientIp, HttpResponse, full_body};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn limits_request_rate() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_second: NonZeroU32::new(2),
+            burst: NonZeroU32::new(3),
+            ..RateLimitConfig::default()
+        });
+        let now = Instant::now();
+        let client = ip("192.0.2.1");
+
+        for _ in 0..3 {
+            assert_eq!(limiter.take_request(client, now), Ok(()));
+        }
+
+        assert_eq!(limiter.take_request(client, now), Err(1));
+        assert_eq!(limiter.take_request(ip("192.0.2.2"), now), Ok(()));
+
+        let later = now + Duration::from_millis(500);
+
+        assert_eq!(limiter.take_request(client, later), Ok(()));
+        assert_eq!(limiter.take_request(client, later), Err(1));
+    }
+
+    #[test]
+    fn delays_bytes_above_rate() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            bytes_per_second: NonZeroU64::new(100),
+            ..RateLimitConfig::default()
+        });
+        let now = Instant::now();
+        let client = ip("192.0.2.1");
+
+        assert_eq!(limiter.consume(client, 100, now), None);
+        assert_eq!(
+            limiter.consume(client, 50, now),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            limiter.consume(client, 50, now + Duration::from_secs(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn caps_transfers() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            max_transfers: NonZeroUsize::new(2),
+            max_transfers_per_ip: NonZeroUsize::new(1),
+            ..RateLimitConfig::default()
+        }));
+        let first = limiter.start_transfer(ip("192.0.2.1")).unwrap();
+
+        assert!(limiter.start_transfer(ip("192.0.2.1")).is_err());
+
+        let second = limiter.start_transfer(ip("192.0.2.2")).unwrap();
+
+        assert!(limiter.start_transfer(ip("192.0.2.3")).is_err());
+
+        drop(first);
+        drop(second);
+
+        assert!(limiter.start_transfer(ip("192.0.2.1")).is_ok());
+    }
+
+    #[test]
+    fn forgets_clients_beyond_capacity() {
+        let mut buckets = Buckets::default();
+        let now = Instant::now();
+
+        buckets.get(ip("192.0.2.1"), 1., now).tokens = 0.;
+
+        for n in 0..MAX_CLIENTS as u32 * 4 {
+            buckets.get(IpAddr::from(n.to_be_bytes()), 1., now);
+            assert!(buckets.current.len() + buckets.previous.len() <= MAX_CLIENTS);
+        }
+
+        assert_eq!(buckets.get(ip("192.0.2.1"), 1., now).tokens, 1.);
+    }
+
+    #[test]
+    fn groups_ipv6_clients_by_network() {
+        assert_eq!(client_key(ip("2001:db8:1:2:aaaa::1")), ip("2001:db8:1:2::"));
+        assert_eq!(client_key(ip("::ffff:192.0.2.1")), ip("192.0.2.1"));
+        assert_eq!(client_key(ip("192.0.2.1")), ip("192.0.2.1"));
+    }
+
+    #[tokio::test]
+    async fn counts_file_transfers_only() {
+        let layer = RateLimitLayer::new(RateLimitConfig {
+            max_transfers_per_ip: NonZeroUsize::new(1),
+            ..RateLimitConfig::default()
+        });
+        let service = layer.layer(service_fn(|req: Request<bool>| async move {
+            let mut response = Response::new(full_body("a"));
+
+            if *req.body() {
+                response.extensions_mut().insert(FileTransfer);
+            }
+
+            Ok::<HttpResponse, Error>(response)
+        }));
+        let request = |file: bool| {
+            let mut request = Request::new(file);
+
+            request.extensions_mut().insert(ClientIp(ip("192.0.2.1")));
+            request
+        };
+
+        let download = service.clone().oneshot(request(true)).await.unwrap();
+        let listing = service.clone().oneshot(request(false)).await.unwrap();
+        let rejected = service.clone().oneshot(request(true)).await.unwrap();
+
+        assert_eq!(download.status(), StatusCode::OK);
+        assert_eq!(listing.status(), StatusCode::OK);
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rejected.headers()[RETRY_AFTER], "1");
+
+        drop(download);
+
+        let download = service.oneshot(request(true)).await.unwrap();
+
+        assert_eq!(download.status(), StatusCode::OK);
+    }
+}
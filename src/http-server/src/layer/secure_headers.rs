@@ -0,0 +1,162 @@
+//! Preset of security headers added to responses which do not set them
+//! already.
+//!
+//! The `Content-Security-Policy` allows the embedded file explorer UI to run:
+//! its WebAssembly module is instantiated from an inline module script and
+//! components set inline styles. `Strict-Transport-Security` is only sent
+//! on connections secured with TLS.
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use http::header::{
+    CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS,
+    X_FRAME_OPTIONS,
+};
+use http::{HeaderName, HeaderValue, Request};
+use tower::{Layer, Service};
+
+use crate::server::{ConnectionInfo, HttpResponse};
+
+const CONTENT_SECURITY_POLICY_VALUE: &str = "default-src 'self'; script-src 'self' 'unsafe-inline' 'wasm-unsafe-eval'; style-src 'self' 'unsafe-inline'; img-src 'self' data: blob:; media-src 'self' blob:; object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'self'";
+
+const HEADERS: [(HeaderName, &str); 4] = [
+    (CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY_VALUE),
+    (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    (X_FRAME_OPTIONS, "SAMEORIGIN"),
+    (REFERRER_POLICY, "no-referrer"),
+];
+
+const STRICT_TRANSPORT_SECURITY_VALUE: &str = "max-age=31536000";
+
+#[derive(Clone, Default)]
+pub struct SecureHeadersLayer;
+
+impl<S> Layer<S> for SecureHeadersLayer {
+    type Service = SecureHeadersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SecureHeadersService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct SecureHeadersService<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for SecureHeadersService<S>
+where
+    S: Service<Request<B>, Response = HttpResponse>,
+    S::Future: Send + 'static,
+{
+    type Response = HttpResponse;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<HttpResponse, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let tls = req
+            .extensions()
+            .get::<ConnectionInfo>()
+            .is_some_and(|info| info.tls);
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut response = future.await?;
+            let headers = response.headers_mut();
+
+            for (name, value) in HEADERS {
+                headers
+                    .entry(name)
+                    .or_insert(HeaderValue::from_static(value));
+            }
+
+            if tls {
+                headers
+                    .entry(STRICT_TRANSPORT_SECURITY)
+                    .or_insert(HeaderValue::from_static(STRICT_TRANSPORT_SECURITY_VALUE));
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Error;
+    use http::header::{STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS};
+    use http::{Request, Response, StatusCode};
+    use tower::{ServiceBuilder, ServiceExt, service_fn};
+
+    use super::{HEADERS, SecureHeadersLayer};
+    use crate::layer::ip_filter::{IpFilter, IpFilterLayer};
+    use crate::server::{ConnectionInfo, HttpResponse, full_body};
+
+    async fn respond(tls: bool, frame_options: Option<&'static str>) -> HttpResponse {
+        let service = ServiceBuilder::new()
+            .layer(SecureHeadersLayer)
+            .service(service_fn(move |_: Request<()>| async move {
+                let mut response = Response::builder();
+
+                if let Some(frame_options) = frame_options {
+                    response = response.header(X_FRAME_OPTIONS, frame_options);
+                }
+
+                Ok::<HttpResponse, Error>(response.body(full_body("ok")).unwrap())
+            }));
+        let mut request = Request::new(());
+
+        request.extensions_mut().insert(ConnectionInfo {
+            remote_addr: SocketAddr::from(([192, 0, 2, 1], 443)),
+            tls,
+            server_name: None,
+        });
+
+        service.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn adds_missing_headers() {
+        let response = respond(false, Some("DENY")).await;
+        let headers = response.headers();
+
+        for (name, _) in HEADERS {
+            assert!(headers.contains_key(&name), "{name}");
+        }
+
+        assert_eq!(headers[X_FRAME_OPTIONS], "DENY");
+        assert_eq!(headers.get_all(X_FRAME_OPTIONS).iter().count(), 1);
+        assert!(!headers.contains_key(STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[tokio::test]
+    async fn sends_hsts_over_tls_only() {
+        let response = respond(true, None).await;
+
+        assert_eq!(
+            response.headers()[STRICT_TRANSPORT_SECURITY],
+            "max-age=31536000"
+        );
+    }
+
+    #[tokio::test]
+    async fn covers_rejected_requests() {
+        let service = ServiceBuilder::new()
+            .layer(SecureHeadersLayer)
+            .layer(IpFilterLayer::new(IpFilter::default()))
+            .service(service_fn(|_: Request<()>| async {
+                Ok::<HttpResponse, Error>(Response::new(full_body("ok")))
+            }));
+        // Requests without a client address are rejected by the filter
+        let response = service.oneshot(Request::new(())).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+    }
+}
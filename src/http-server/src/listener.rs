@@ -0,0 +1,324 @@
+//! Sockets accepting client connections, either TCP sockets or Unix domain
+//! sockets, bound by the server or inherited from the parent process such as
+//! systemd.
+//!
+//! Unix domain sockets left behind by a previous process are removed before
+//! binding, and the socket file is removed once the listener is dropped.
+//! Inherited sockets are left in place, as they belong to the parent process.
+//!
+//! Peers of Unix domain sockets have no IP address, so they are expected to
+//! be reverse proxies: the client address is read from `X-Forwarded-For`
+//! whatever the trusted proxies, and requests without it are attributed to
+//! `127.0.0.1`, sharing the same rate limits and IP filter decisions.
+#[cfg(unix)]
+use std::fs::{Permissions, remove_file, rename, set_permissions, symlink_metadata};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+#[cfg(unix)]
+use tempfile::Builder as TempDirBuilder;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+#[cfg(unix)]
+use tracing::{debug, warn};
+
+use crate::config::{Listen, SocketMode};
+
+/// Bidirectional stream served by the HTTP connection, such as a plain
+/// `TcpStream` or a TLS stream wrapping it.
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// Address attributed to peers connected through a Unix domain socket,
+/// which are always local.
+const UNIX_PEER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        /// Socket file removed once the listener is dropped, `None` for
+        /// inherited sockets
+        path: Option<PathBuf>,
+    },
+}
+
+impl Listener {
+    /// Binds the socket at `listen`, setting the permissions of Unix domain
+    /// sockets to `mode` when provided.
+    pub async fn bind(listen: &Listen, mode: Option<SocketMode>) -> Result<Self> {
+        if mode.is_some() && !matches!(listen, Listen::Unix(_)) {
+            bail!("The socket mode only applies to Unix domain sockets bound by the server.");
+        }
+
+        match listen {
+            Listen::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("Failed to bind to {addr}"))?;
+
+                Ok(Listener::Tcp(listener))
+            }
+            #[cfg(unix)]
+            Listen::Unix(path) => {
+                remove_stale_socket(path)?;
+
+                let listener = match mode {
+                    Some(mode) => bind_unix_with_mode(path, mode)?,
+                    None => UnixListener::bind(path)
+                        .with_context(|| format!("Failed to bind to {}", path.display()))?,
+                };
+
+                Ok(Listener::Unix {
+                    listener,
+                    path: Some(path.clone()),
+                })
+            }
+            #[cfg(unix)]
+            Listen::Fd(fd) => Self::from_fd(*fd),
+            #[cfg(not(unix))]
+            Listen::Unix(_) | Listen::Fd(_) => {
+                bail!("Unix domain sockets are not supported on this platform.")
+            }
+        }
+    }
+
+    /// Checks whether peers connect through a Unix domain socket, and are
+    /// therefore treated as trusted proxies.
+    pub fn is_unix(&self) -> bool {
+        match self {
+            Listener::Tcp(_) => false,
+            #[cfg(unix)]
+            Listener::Unix { .. } => true,
+        }
+    }
+
+    /// Takes ownership of the listening socket `fd` inherited from the parent
+    /// process, either a TCP or a Unix domain socket.
+    #[cfg(unix)]
+    fn from_fd(fd: RawFd) -> Result<Self> {
+        let family = socket_family(fd)
+            .with_context(|| format!("File descriptor {fd} is not a listening socket"))?;
+
+        // SAFETY: `getsockname` succeeded, so `fd` is an open socket. It is
+        // owned by the listener from now on and not used anywhere else.
+        unsafe {
+            // Inherited descriptors are not closed on exec by default
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+
+            match family {
+                libc::AF_INET | libc::AF_INET6 => {
+                    let listener = std::net::TcpListener::from_raw_fd(fd);
+
+                    listener.set_nonblocking(true)?;
+
+                    Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+                }
+                libc::AF_UNIX => {
+                    let listener = std::os::unix::net::UnixListener::from_raw_fd(fd);
+
+                    listener.set_nonblocking(true)?;
+
+                    Ok(Listener::Unix {
+                        listener: UnixListener::from_std(listener)?,
+                        path: None,
+                    })
+                }
+                family => bail!("File descriptor {fd} has unsupported address family {family}."),
+            }
+        }
+    }
+
+    /// Accepts a connection, along with the address of the peer.
+    pub async fn accept(&self) -> io::Result<(Box<dyn Io>, SocketAddr)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+
+                Ok((Box::new(stream), addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                let (stream, _) = listener.accept().await?;
+
+                Ok((Box::new(stream), UNIX_PEER_ADDR))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix {
+            path: Some(path), ..
+        } = self
+            && let Err(err) = remove_file(&*path)
+        {
+            warn!(%err, path = %path.display(), "Failed to remove socket file");
+        }
+    }
+}
+
+/// Reads the address family of the socket `fd`, such as `AF_INET`.
+#[cfg(unix)]
+fn socket_family(fd: RawFd) -> io::Result<libc::c_int> {
+    // SAFETY: `sockaddr_storage` is valid when zeroed and large enough for
+    // any address, and `len` holds its size.
+    unsafe {
+        let mut addr: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+        if libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(addr.ss_family as libc::c_int)
+    }
+}
+
+/// Binds a Unix domain socket at `path` with the permissions `mode`.
+///
+/// The socket is bound in a private directory next to `path` and moved to
+/// `path` once its permissions are set, so clients cannot connect to it in
+/// the meantime whatever the umask of the process.
+#[cfg(unix)]
+fn bind_unix_with_mode(path: &Path, SocketMode(mode): SocketMode) -> Result<UnixListener> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = TempDirBuilder::new()
+        .prefix(".http-server-")
+        .tempdir_in(parent)
+        .with_context(|| format!("Failed to create a directory in {}", parent.display()))?;
+    let temp_path = dir.path().join("socket");
+    let listener = UnixListener::bind(&temp_path)
+        .with_context(|| format!("Failed to bind to {}", path.display()))?;
+
+    set_permissions(&temp_path, Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+    rename(&temp_path, path).with_context(|| format!("Failed to bind to {}", path.display()))?;
+
+    Ok(listener)
+}
+
+/// Removes the socket file at `path` when no process is listening on it
+/// anymore, such as after a crash. Fails if another process is still
+/// listening, or if `path` is not a socket.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+
+    if !metadata.file_type().is_socket() {
+        bail!("{} exists and is not a socket.", path.display());
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => bail!("Another process is listening on {}.", path.display()),
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+            remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+            debug!(path = %path.display(), "Removed stale socket");
+
+            Ok(())
+        }
+        Err(err) => Err(err).with_context(|| format!("Failed to connect to {}", path.display())),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs::{metadata, read_dir, write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    use tempfile::TempDir;
+
+    use super::{Listener, remove_stale_socket};
+    use crate::config::{Listen, SocketMode};
+
+    #[test]
+    fn removes_stale_sockets() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("http-server.sock");
+
+        drop(UnixListener::bind(&path).unwrap());
+
+        assert!(path.exists());
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+        remove_stale_socket(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_sockets_in_use() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("http-server.sock");
+        let _listener = UnixListener::bind(&path).unwrap();
+        let err = remove_stale_socket(&path).unwrap_err();
+
+        assert!(err.to_string().contains("Another process"), "{err}");
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn keeps_other_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("http-server.sock");
+
+        write(&path, "data").unwrap();
+
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn binds_unix_sockets_with_mode() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("http-server.sock");
+        let listen = Listen::Unix(path.clone());
+        let listener = Listener::bind(&listen, Some(SocketMode(0o600)))
+            .await
+            .unwrap();
+
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        // Only the socket is left in the directory
+        assert_eq!(read_dir(dir.path()).unwrap().count(), 1);
+
+        drop(listener);
+
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_socket_mode_for_tcp() {
+        let listen = Listen::Tcp("127.0.0.1:0".parse().unwrap());
+
+        assert!(
+            Listener::bind(&listen, Some(SocketMode(0o600)))
+                .await
+                .is_err()
+        );
+        assert!(Listener::bind(&listen, None).await.is_ok());
+    }
+}
@@ -0,0 +1,29 @@
+//! Diagnostic log events emitted through `tracing`.
+use std::io::{IsTerminal, stdout};
+
+use anyhow::{Context, Error, Result};
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LogFormat;
+
+/// Installs the global subscriber writing events which pass the `level`
+/// filter to standard output in the provided `format`. Colors are only used
+/// when standard output is a terminal.
+///
+/// The `level` filter uses the same syntax as `RUST_LOG`, so it may either be
+/// a level such as `debug`, or per target directives such as
+/// `http_server=debug,warn`.
+pub fn init(level: &str, format: LogFormat) -> Result<()> {
+    let filter =
+        EnvFilter::try_new(level).with_context(|| format!("Invalid log level \"{level}\"."))?;
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(stdout().is_terminal());
+
+    match format {
+        LogFormat::Text => subscriber.try_init(),
+        LogFormat::Json => subscriber.json().try_init(),
+        LogFormat::Compact => subscriber.compact().try_init(),
+    }
+    .map_err(Error::msg)
+}
@@ -1,7 +1,13 @@
 pub mod cli;
 pub mod config;
 pub mod handler;
+pub mod idle_timeout;
+pub mod layer;
+pub mod listener;
+pub mod logging;
 pub mod server;
+pub mod systemd;
+pub mod tls;
 
 use anyhow::Result;
 use clap::Parser;
@@ -10,9 +16,6 @@ use self::cli::Cli;
 use self::cli::command::Command;
 
 fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
     let args = Cli::parse();
 
     match args.command {
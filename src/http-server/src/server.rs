@@ -1,30 +1,82 @@
+use std::io;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::path::PathBuf;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
-use http_body_util::Full;
+use anyhow::{Error, Result};
+use http::{HeaderMap, StatusCode};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
 use hyper::body::{Bytes, Incoming};
-use hyper::server::conn::http1;
-use hyper::{Method, Request, Response};
-use hyper_util::rt::TokioIo;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
 use hyper_util::service::TowerToHyperService;
 use local_ip_address::local_ip;
-use tokio::net::TcpListener;
+use tokio::signal;
+use tokio::sync::Semaphore;
+use tokio::time::sleep;
 use tower::ServiceBuilder;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::timeout::TimeoutLayer;
+use tracing::{debug, info, warn};
 
-use crate::config::{Config, Service};
-use crate::handler::Handler;
+use crate::config::{Config, DenyAction, Listen, RateLimitConfig, Service};
 use crate::handler::file_explorer::FileExplorer;
 use crate::handler::file_server::{FileServer, FileServerConfig};
+use crate::handler::proxy::Proxy;
+use crate::handler::router::Router;
+use crate::handler::virtual_host::VirtualHosts;
+use crate::handler::{Handler, HandlerService, ServiceHandler};
+use crate::idle_timeout::{IdleTimeout, Requests};
+use crate::layer::access_log::{AccessLog, AccessLogLayer};
+use crate::layer::basic_auth::BasicAuthLayer;
+use crate::layer::compression::make_compression_layer;
+use crate::layer::cors::make_cors_layer;
+use crate::layer::headers::{HeaderRulesLayer, ResponseHeadersLayer};
+use crate::layer::ip_filter::{IpFilter, IpFilterLayer, client_ip, forwarded_client_ip};
+use crate::layer::rate_limit::RateLimitLayer;
+use crate::layer::secure_headers::SecureHeadersLayer;
+use crate::listener::{Io, Listener};
+use crate::systemd;
+use crate::tls::make_tls_acceptor;
 
 pub type HttpRequest = Request<Incoming>;
-pub type HttpResponse = Response<Full<Bytes>>;
+pub type HttpBody = BoxBody<Bytes, Error>;
+pub type HttpResponse = Response<HttpBody>;
 
 const ALL_INTERFACES_IPV4: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 0);
 
+/// Time to wait before accepting connections again after a failure.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Creates a `HttpBody` from a buffer held in memory.
+pub fn full_body(bytes: impl Into<Bytes>) -> HttpBody {
+    Full::new(bytes.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// Details of the connection a request was received on, available to
+/// handlers and layers as a request extension.
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    /// Address of the connected peer.
+    pub remote_addr: SocketAddr,
+    /// Whether the connection is secured with TLS.
+    pub tls: bool,
+    /// Server name requested through TLS SNI, if any.
+    pub server_name: Option<String>,
+}
+
+/// Address of the client which sent a request, available to handlers and
+/// layers as a request extension.
+///
+/// Requests received through a trusted proxy are attributed to the address
+/// provided in the `X-Forwarded-For` header.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
 pub struct Server {
     config: Config,
 }
@@ -34,60 +86,754 @@ impl Server {
         Server { config }
     }
 
+    /// Serves requests until the process receives `SIGINT` or `SIGTERM`.
     pub async fn run(&self) -> Result<()> {
-        let addr = SocketAddr::from((self.config.host, self.config.port));
-        let listener = TcpListener::bind(addr).await?;
+        self.run_until(shutdown_signal()).await
+    }
 
-        println!("Listening on http://{addr}");
+    /// Serves requests until `shutdown` resolves, then waits for active
+    /// connections to complete.
+    async fn run_until(&self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let listener = Listener::bind(&self.config.listen, self.config.socket_mode).await?;
+        let http2 = self.config.http2.is_enabled();
+        let tls_acceptor = self
+            .config
+            .tls
+            .as_ref()
+            .map(|tls| make_tls_acceptor(tls, &self.config.virtual_hosts, http2))
+            .transpose()?;
+        let connection_builder = self.make_connection_builder();
+        let compression = make_compression_layer(&self.config.compression);
+        let cors = make_cors_layer(&self.config.cors)?;
+        let header_rules = (!self.config.header_rules.is_empty())
+            .then(|| HeaderRulesLayer::new(self.config.header_rules.clone()));
+        let secure_headers = self.config.secure_headers.then_some(SecureHeadersLayer);
+        let ip_filter = (!self.config.ip_filter.is_empty())
+            .then(|| IpFilterLayer::new(self.config.ip_filter.clone()));
+        let rate_limit = self
+            .config
+            .rate_limit
+            .is_enabled()
+            .then(|| RateLimitLayer::new(self.config.rate_limit.clone()));
+        let request_timeout =
+            self.config.connection.request_timeout().map(|timeout| {
+                TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, timeout)
+            });
+        let header_read_timeout = self.config.connection.header_read_timeout();
+        let idle_timeout = self.config.connection.idle_timeout();
+        let connections = self
+            .config
+            .connection
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max.get())));
+        let trusted_proxies: Arc<[_]> = self.config.trusted_proxies.clone().into();
+        let access_log = self
+            .config
+            .access_log
+            .is_enabled()
+            .then(|| AccessLog::new(&self.config.access_log).map(Arc::new))
+            .transpose()?;
+        let scheme = if tls_acceptor.is_some() {
+            "https"
+        } else {
+            "http"
+        };
 
-        if matches!(addr.ip(), IpAddr::V4(ALL_INTERFACES_IPV4))
-            && let Ok(local_ip) = local_ip()
-        {
-            println!("Local Network on http://{}:{}", local_ip, self.config.port);
-        }
+        match &self.config.listen {
+            Listen::Tcp(addr) => {
+                info!("Listening on {scheme}://{addr}");
 
-        let root_dir = PathBuf::from_str("./")?;
-        let service: Arc<dyn Handler> = match self.config.service {
-            Service::FileExplorer { .. } => {
-                let file_explorer = FileExplorer::new(root_dir);
-                Arc::new(file_explorer)
-            }
-            Service::FileServer { .. } => {
-                let file_server = FileServer::new(FileServerConfig {
-                    root_dir,
-                    index: false,
-                    spa: false,
-                });
-                Arc::new(file_server)
+                if matches!(addr.ip(), IpAddr::V4(ALL_INTERFACES_IPV4))
+                    && let Ok(local_ip) = local_ip()
+                {
+                    info!("Local Network on {scheme}://{}:{}", local_ip, addr.port());
+                }
             }
-        };
+            Listen::Unix(path) => info!("Listening on {scheme} over unix:{}", path.display()),
+            Listen::Fd(fd) => info!("Listening on {scheme} over inherited socket {fd}"),
+        }
+
+        let service = self.make_handler();
+
+        if let Some(access_log) = &access_log {
+            reopen_on_hangup(Arc::clone(access_log));
+        }
+
+        let unix_peers = listener.is_unix();
+        let graceful = GracefulShutdown::new();
+        tokio::pin!(shutdown);
+
+        systemd::notify(systemd::READY);
 
         loop {
-            let service: Arc<dyn Handler> = Arc::clone(&service);
-            let (stream, _) = listener.accept().await?;
-            let io = TokioIo::new(stream);
-            let cors = if self.config.cors {
-                Some(
-                    CorsLayer::new()
-                        .allow_methods([Method::GET, Method::POST])
-                        .allow_origin(Any),
-                )
-            } else {
-                None
+            // Connections beyond the limit are left in the listen backlog
+            // until a permit is released
+            let permit = match &connections {
+                Some(connections) => tokio::select! {
+                    permit = Arc::clone(connections).acquire_owned() => {
+                        Some(permit.expect("Connection semaphore closed"))
+                    }
+                    _ = &mut shutdown => break,
+                },
+                None => None,
+            };
+            let (stream, remote_addr) = tokio::select! {
+                conn = listener.accept() => match conn {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        // Errors such as running out of file descriptors or
+                        // aborted connections are transient, so accepting
+                        // resumes after a delay rather than exiting
+                        warn!(%err, "Failed to accept connection");
+
+                        tokio::select! {
+                            _ = sleep(ACCEPT_ERROR_DELAY) => continue,
+                            _ = &mut shutdown => break,
+                        }
+                    }
+                },
+                _ = &mut shutdown => break,
             };
 
+            // Peers of Unix domain sockets are proxies, so requests are
+            // filtered by the forwarded client address instead
+            if !unix_peers && self.is_dropped(remote_addr.ip()) {
+                debug!(%remote_addr, "Connection rejected by IP filter");
+                continue;
+            }
+
+            let service: Arc<dyn Handler> = Arc::clone(&service);
+            let watcher = graceful.watcher();
+            let tls_acceptor = tls_acceptor.clone();
+            let connection_builder = connection_builder.clone();
+            let compression = compression.clone();
+            let access_log = AccessLogLayer::new(access_log.clone());
+            let cors = cors.clone();
+            let header_rules = header_rules.clone();
+            let secure_headers = secure_headers.clone();
+            let ip_filter = ip_filter.clone();
+            let rate_limit = rate_limit.clone();
+            let trusted_proxies = Arc::clone(&trusted_proxies);
+
             tokio::spawn(async move {
-                let svc =
-                    tower::service_fn(|req: Request<Incoming>| async { service.handle(req).await });
+                let _permit = permit;
+                let (io, connection_info): (Box<dyn Io>, ConnectionInfo) = match tls_acceptor {
+                    Some(tls_acceptor) => {
+                        match with_timeout(header_read_timeout, tls_acceptor.accept(stream)).await {
+                            Ok(stream) => {
+                                let server_name =
+                                    stream.get_ref().1.server_name().map(String::from);
+
+                                (
+                                    Box::new(stream),
+                                    ConnectionInfo {
+                                        remote_addr,
+                                        tls: true,
+                                        server_name,
+                                    },
+                                )
+                            }
+                            Err(err) if is_timeout(&err) => {
+                                debug!(%err, %remote_addr, "TLS handshake timed out");
+                                return;
+                            }
+                            Err(err) => {
+                                warn!(%err, %remote_addr, "TLS handshake failed");
+                                return;
+                            }
+                        }
+                    }
+                    None => (
+                        Box::new(stream),
+                        ConnectionInfo {
+                            remote_addr,
+                            tls: false,
+                            server_name: None,
+                        },
+                    ),
+                };
+                let requests = Requests::default();
+                let io: Box<dyn Io> = if idle_timeout.is_some() || header_read_timeout.is_some() {
+                    Box::new(IdleTimeout::new(
+                        io,
+                        idle_timeout,
+                        header_read_timeout,
+                        requests.clone(),
+                    ))
+                } else {
+                    io
+                };
+                let io = TokioIo::new(io);
+
+                let svc = ServiceBuilder::new()
+                    .layer(requests)
+                    .map_request(move |mut req: HttpRequest| {
+                        let client_ip = if unix_peers {
+                            forwarded_client_ip(remote_addr.ip(), req.headers(), &trusted_proxies)
+                        } else {
+                            client_ip(remote_addr.ip(), req.headers(), &trusted_proxies)
+                        };
 
-                let svc = ServiceBuilder::new().option_layer(cors).service(svc);
+                        req.extensions_mut().insert(connection_info.clone());
+                        req.extensions_mut().insert(ClientIp(client_ip));
+                        req
+                    })
+                    .layer(access_log)
+                    // Outside the layers answering on their own, such as the
+                    // IP filter or CORS preflight, so their responses get the
+                    // headers too. Rules apply last so they can override the
+                    // preset.
+                    .option_layer(header_rules)
+                    .option_layer(secure_headers)
+                    .option_layer(compression)
+                    .option_layer(ip_filter)
+                    .option_layer(rate_limit)
+                    .option_layer(request_timeout)
+                    .option_layer(cors)
+                    .service(HandlerService::new(service));
 
                 let svc = TowerToHyperService::new(svc);
 
-                if let Err(err) = http1::Builder::new().serve_connection(io, svc).await {
-                    eprintln!("server error: {err}");
+                let conn = connection_builder.serve_connection(io, svc);
+
+                if let Err(err) = watcher.watch(conn).await {
+                    if is_timeout(err.as_ref()) {
+                        debug!(%err, %remote_addr, "Connection timed out");
+                    } else {
+                        warn!(%err, %remote_addr, "Failed to serve connection");
+                    }
                 }
             });
         }
+
+        systemd::notify(systemd::STOPPING);
+        drop(listener);
+        self.drain(graceful).await;
+
+        Ok(())
+    }
+
+    /// Waits for active connections to complete up to the configured
+    /// shutdown timeout.
+    async fn drain(&self, graceful: GracefulShutdown) {
+        let timeout = self.config.shutdown_timeout;
+
+        info!(
+            connections = graceful.count(),
+            "Shutting down, waiting up to {}s for active connections to complete",
+            timeout.as_secs()
+        );
+
+        tokio::select! {
+            _ = graceful.shutdown() => {
+                info!("All connections closed");
+            }
+            _ = sleep(timeout) => {
+                warn!("Timed out waiting for connections to close");
+            }
+        }
+    }
+
+    /// Checks whether connections from `ip` are closed as soon as accepted.
+    /// Connections from trusted proxies are always accepted, and requests
+    /// forwarded by them are filtered by the client address instead.
+    fn is_dropped(&self, ip: IpAddr) -> bool {
+        self.config.deny_action == DenyAction::Drop
+            && !self.config.ip_filter.is_allowed(ip)
+            && !self
+                .config
+                .trusted_proxies
+                .iter()
+                .any(|network| network.contains(ip))
+    }
+
+    /// Creates the root `Handler`, dispatching requests to virtual hosts by
+    /// host name and to the routing table otherwise.
+    fn make_handler(&self) -> Arc<dyn Handler> {
+        let router = self.make_router();
+
+        if self.config.virtual_hosts.is_empty() {
+            return router;
+        }
+
+        let virtual_hosts = self
+            .config
+            .virtual_hosts
+            .iter()
+            .map(|virtual_host| {
+                let handler = self.make_service_handler(
+                    &virtual_host.service,
+                    String::new(),
+                    &virtual_host.headers,
+                    &IpFilter::default(),
+                    &RateLimitConfig::default(),
+                    self.config.max_upload_size,
+                );
+
+                (virtual_host.hosts.clone(), handler)
+            })
+            .collect();
+
+        Arc::new(VirtualHosts::new(virtual_hosts, router))
+    }
+
+    /// Creates the `Router` dispatching requests to each configured route.
+    fn make_router(&self) -> Arc<dyn Handler> {
+        let routes = self
+            .config
+            .routes
+            .iter()
+            .map(|route| {
+                let base_path = Router::normalize_prefix(&route.prefix);
+                let handler = self.make_service_handler(
+                    &route.service,
+                    base_path,
+                    &HeaderMap::new(),
+                    &route.ip_filter,
+                    &route.rate_limit,
+                    route.max_upload_size.or(self.config.max_upload_size),
+                );
+
+                (route.prefix.clone(), handler)
+            })
+            .collect();
+
+        Arc::new(Router::new(routes))
+    }
+
+    /// Creates the `Handler` for a `Service` mounted at `base_path`, wrapped
+    /// with the layers configured for such service.
+    fn make_service_handler(
+        &self,
+        service: &Service,
+        base_path: String,
+        headers: &HeaderMap,
+        ip_filter: &IpFilter,
+        rate_limit: &RateLimitConfig,
+        max_upload_size: Option<u64>,
+    ) -> Arc<dyn Handler> {
+        let handler: Arc<dyn Handler> = match service {
+            Service::FileExplorer { root_directory, .. } => {
+                Arc::new(FileExplorer::new(root_directory.clone(), max_upload_size))
+            }
+            Service::FileServer { root_directory, .. } => {
+                Arc::new(FileServer::new(FileServerConfig {
+                    root_dir: root_directory.clone(),
+                    base_path,
+                    index: false,
+                    spa: false,
+                    cache_control: self.config.cache_control.clone(),
+                }))
+            }
+            Service::Proxy { upstream, .. } => Arc::new(Proxy::new(upstream.clone())),
+        };
+
+        if service.basic_auth().is_none()
+            && headers.is_empty()
+            && ip_filter.is_empty()
+            && !rate_limit.is_enabled()
+        {
+            return handler;
+        }
+
+        let headers = (!headers.is_empty()).then(|| ResponseHeadersLayer::new(headers.clone()));
+        let ip_filter = (!ip_filter.is_empty()).then(|| IpFilterLayer::new(ip_filter.clone()));
+        let rate_limit = rate_limit
+            .is_enabled()
+            .then(|| RateLimitLayer::new(rate_limit.clone()));
+
+        Arc::new(ServiceHandler::new(
+            ServiceBuilder::new()
+                .option_layer(ip_filter)
+                .option_layer(rate_limit)
+                .option_layer(headers)
+                .option_layer(service.basic_auth().map(BasicAuthLayer::new))
+                .service(HandlerService::new(handler)),
+        ))
+    }
+
+    /// Creates the HTTP connection builder which serves HTTP/1.1 and, unless
+    /// disabled, HTTP/2 on the same connection by detecting the protocol.
+    fn make_connection_builder(&self) -> auto::Builder<TokioExecutor> {
+        let config = &self.config.http2;
+        let connection = &self.config.connection;
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        let mut http1 = builder.http1();
+
+        http1
+            .timer(TokioTimer::new())
+            .header_read_timeout(connection.header_read_timeout());
+
+        if let Some(max) = connection.max_head_size {
+            http1.max_buf_size(max);
+        }
+
+        if !config.is_enabled() {
+            return builder.http1_only();
+        }
+
+        let mut http2 = builder.http2();
+
+        if let Some(max) = config.max_concurrent_streams {
+            http2.max_concurrent_streams(max);
+        }
+
+        if let Some(max) = connection.max_head_size {
+            http2.max_header_list_size(u32::try_from(max).unwrap_or(u32::MAX));
+        }
+
+        http2
+            .timer(TokioTimer::new())
+            .initial_stream_window_size(config.initial_stream_window_size)
+            .initial_connection_window_size(config.initial_connection_window_size);
+
+        builder
+    }
+}
+
+/// Runs `future` to completion, failing with `TimedOut` once `timeout` elapses.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?,
+        None => future.await,
+    }
+}
+
+/// Checks whether a connection failed because the client exceeded a timeout.
+fn is_timeout(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<hyper::Error>()
+            && err.is_timeout()
+        {
+            return true;
+        }
+
+        if let Some(err) = err.downcast_ref::<io::Error>()
+            && err.kind() == io::ErrorKind::TimedOut
+        {
+            return true;
+        }
+
+        source = err.source();
+    }
+
+    false
+}
+
+/// Reopens the access log file whenever the process receives `SIGHUP`, as
+/// sent by `logrotate` once the file has been rotated.
+#[cfg(unix)]
+fn reopen_on_hangup(access_log: Arc<AccessLog>) {
+    tokio::spawn(async move {
+        let mut sighup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(err) => {
+                warn!(%err, "Failed to listen for SIGHUP");
+                return;
+            }
+        };
+
+        while sighup.recv().await.is_some() {
+            match access_log.reopen() {
+                Ok(()) => info!("Reopened access log"),
+                Err(err) => warn!(%err, "Failed to reopen access log"),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn reopen_on_hangup(_: Arc<AccessLog>) {}
+
+/// Resolves when the process receives either `SIGINT` (Ctrl+C) or `SIGTERM`.
+///
+/// Handlers are installed when called rather than when first polled, so a
+/// service manager stopping the server right after it reported being ready
+/// still triggers a graceful shutdown.
+#[cfg(unix)]
+fn shutdown_signal() -> impl Future<Output = ()> {
+    let sigint = signal_received(signal::unix::SignalKind::interrupt(), "SIGINT");
+    let sigterm = signal_received(signal::unix::SignalKind::terminate(), "SIGTERM");
+
+    async move {
+        tokio::select! {
+            _ = sigint => {}
+            _ = sigterm => {}
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal_received(kind: signal::unix::SignalKind, name: &str) -> impl Future<Output = ()> {
+    let signal = signal::unix::signal(kind);
+
+    if let Err(err) = &signal {
+        warn!(%err, "Failed to listen for {name}");
+    }
+
+    async move {
+        match signal {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    if let Err(err) = signal::ctrl_c().await {
+        warn!(%err, "Failed to listen for Ctrl+C");
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs::{create_dir, read_to_string};
+    use std::future::pending;
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use clap::Parser;
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UnixStream};
+    use tokio::sync::oneshot;
+    use tokio::time::{sleep, timeout};
+
+    use super::Server;
+    use crate::cli::command::start::StartOpt;
+    use crate::config::ConfigFile;
+
+    /// Starts an upstream answering its first request once `release` is
+    /// sent, and notifies `received` when the request arrives.
+    async fn slow_upstream(
+        received: oneshot::Sender<()>,
+        release: oneshot::Receiver<()>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+
+            let _ = stream.read(&mut buf).await.unwrap();
+            received.send(()).unwrap();
+            let _ = release.await;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\nconnection: close\r\n\r\ndone")
+                .await
+                .unwrap();
+        });
+
+        format!("http://{addr}")
+    }
+
+    /// Creates a server listening on a socket at `path`, configured with the
+    /// `start` command `args`.
+    fn server(path: &Path, args: &[&str]) -> Server {
+        let listen = format!("unix:{}", path.display());
+        let opt =
+            StartOpt::try_parse_from([&["start", "--listen", &listen], args].concat()).unwrap();
+        let file = ConfigFile::from_str("").unwrap();
+
+        Server::new(opt.resolve_config(&file).unwrap())
+    }
+
+    /// Creates a server proxying to `upstream` over a socket at `path`.
+    fn proxy_server(path: &Path, upstream: &str, shutdown_timeout: &str) -> Server {
+        server(
+            path,
+            &[
+                "--service",
+                "proxy",
+                "--upstream",
+                upstream,
+                "--shutdown-timeout",
+                shutdown_timeout,
+            ],
+        )
+    }
+
+    /// Connects to the socket at `path` once the server is listening and
+    /// sends a request.
+    async fn send_request(path: &Path) -> UnixStream {
+        let mut stream = connect(path).await;
+
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        stream
+    }
+
+    /// Connects to the socket at `path` once the server is listening.
+    async fn connect(path: &Path) -> UnixStream {
+        while !path.exists() {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        UnixStream::connect(path).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn drains_active_connections_on_shutdown() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("http-server.sock");
+        let (received_tx, received_rx) = oneshot::channel();
+        let (release_tx, release_rx) = oneshot::channel();
+        let upstream = slow_upstream(received_tx, release_rx).await;
+        let server = proxy_server(&path, &upstream, "30");
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            server
+                .run_until(async {
+                    let _ = shutdown_rx.await;
+                })
+                .await
+        });
+        let mut stream = send_request(&path).await;
+
+        received_rx.await.unwrap();
+        shutdown_tx.send(()).unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        // The server waits for the request in flight
+        assert!(!server.is_finished());
+        // And stops accepting connections
+        assert!(UnixStream::connect(&path).await.is_err());
+
+        release_tx.send(()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("done"), "{response}");
+        timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn stops_waiting_after_shutdown_timeout() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("http-server.sock");
+        let (received_tx, received_rx) = oneshot::channel();
+        let (_release_tx, release_rx) = oneshot::channel();
+        let upstream = slow_upstream(received_tx, release_rx).await;
+        let server = proxy_server(&path, &upstream, "1");
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            server
+                .run_until(async {
+                    let _ = shutdown_rx.await;
+                })
+                .await
+        });
+        let _stream = send_request(&path).await;
+
+        received_rx.await.unwrap();
+        shutdown_tx.send(()).unwrap();
+
+        timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn serves_long_uploads() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("http-server.sock");
+        let root = dir.path().join("root");
+
+        create_dir(&root).unwrap();
+
+        let server = server(
+            &path,
+            &["--service", "file-explorer", root.to_str().unwrap()],
+        );
+
+        tokio::spawn(async move { server.run_until(pending()).await });
+
+        let mut stream = connect(&path).await;
+
+        stream
+            .write_all(
+                b"POST /api/v1/ HTTP/1.1\r\nhost: localhost\r\nx-file-name: slow.txt\r\n\
+                  transfer-encoding: chunked\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        // Ten minutes of a healthy but slow upload
+        for _ in 0..20 {
+            sleep(Duration::from_secs(30)).await;
+            stream.write_all(b"1\r\na\r\n").await.unwrap();
+        }
+
+        stream.write_all(b"0\r\n\r\n").await.unwrap();
+
+        let mut response = [0; 1024];
+        let len = stream.read(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response[..len]);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert_eq!(
+            read_to_string(root.join("slow.txt")).unwrap(),
+            "a".repeat(20)
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_unix_peers_by_forwarded_address() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("http-server.sock");
+        let server = server(
+            &path,
+            &[
+                "--allow",
+                "192.0.2.0/24",
+                "--service",
+                "file-server",
+                dir.path().to_str().unwrap(),
+            ],
+        );
+
+        tokio::spawn(async move { server.run_until(pending()).await });
+
+        let status = async |forwarded_for: Option<&str>| {
+            let mut stream = connect(&path).await;
+            let forwarded_for = forwarded_for
+                .map(|ip| format!("x-forwarded-for: {ip}\r\n"))
+                .unwrap_or_default();
+            let request = format!(
+                "GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n{forwarded_for}\r\n"
+            );
+            let mut response = String::new();
+
+            stream.write_all(request.as_bytes()).await.unwrap();
+            stream.read_to_string(&mut response).await.unwrap();
+            response[9..12].to_string()
+        };
+
+        assert_eq!(status(Some("192.0.2.7")).await, "200");
+        assert_eq!(status(Some("198.51.100.1")).await, "403");
+        assert_eq!(status(None).await, "403");
     }
 }
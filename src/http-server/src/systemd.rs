@@ -0,0 +1,168 @@
+//! Integration with systemd service units.
+//!
+//! Listening sockets can be passed by systemd through socket activation
+//! (`LISTEN_FDS` and `LISTEN_PID`), and `Type=notify` units are told when the
+//! server is ready and when it is stopping through `NOTIFY_SOCKET`.
+#[cfg(unix)]
+use std::env;
+#[cfg(unix)]
+use std::ffi::OsStr;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::process;
+
+#[cfg(unix)]
+use tracing::warn;
+
+/// First file descriptor passed through socket activation.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Notification sent once the server is accepting connections.
+pub const READY: &str = "READY=1";
+
+/// Notification sent once the server starts shutting down.
+pub const STOPPING: &str = "STOPPING=1";
+
+/// Returns the file descriptor of the socket passed by systemd to this
+/// process, if any. Only the first socket is used when several are passed.
+#[cfg(unix)]
+pub fn listen_fd() -> Option<i32> {
+    listen_fd_from(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+    )
+}
+
+#[cfg(unix)]
+fn listen_fd_from(listen_pid: Option<&str>, listen_fds: Option<&str>) -> Option<i32> {
+    let pid = listen_pid?.parse::<u32>().ok()?;
+
+    // Variables set for a parent process are not meant for this one
+    if pid != process::id() {
+        return None;
+    }
+
+    let count = listen_fds?.parse::<i32>().ok()?;
+
+    if count > 1 {
+        warn!("Received {count} sockets from systemd, only the first one is used");
+    }
+
+    (count > 0).then_some(SD_LISTEN_FDS_START)
+}
+
+#[cfg(not(unix))]
+pub fn listen_fd() -> Option<i32> {
+    None
+}
+
+/// Sends `state` to the service manager, when running under a `Type=notify`
+/// unit.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    if let Err(err) = send(&path, state) {
+        warn!(%err, state, "Failed to notify systemd");
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+#[cfg(unix)]
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+
+    // Names starting with `@` refer to the abstract namespace
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+
+            return Ok(());
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+
+            return Err(io::Error::from(io::ErrorKind::Unsupported));
+        }
+    }
+
+    socket.send_to(state.as_bytes(), path)?;
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+    use std::process;
+
+    use tempfile::TempDir;
+
+    use super::{READY, SD_LISTEN_FDS_START, listen_fd_from, send};
+
+    #[test]
+    fn resolves_listen_fd() {
+        let pid = process::id().to_string();
+
+        assert_eq!(
+            listen_fd_from(Some(&pid), Some("1")),
+            Some(SD_LISTEN_FDS_START)
+        );
+        assert_eq!(
+            listen_fd_from(Some(&pid), Some("2")),
+            Some(SD_LISTEN_FDS_START)
+        );
+        assert_eq!(listen_fd_from(Some(&pid), Some("0")), None);
+        assert_eq!(listen_fd_from(Some(&pid), None), None);
+        assert_eq!(listen_fd_from(Some("1"), Some("1")), None);
+        assert_eq!(listen_fd_from(None, Some("1")), None);
+    }
+
+    #[test]
+    fn sends_notifications() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        let mut buf = [0; 16];
+
+        send(path.as_os_str(), READY).unwrap();
+
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], READY.as_bytes());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sends_notifications_to_abstract_sockets() {
+        use std::ffi::OsStr;
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let name = format!("http-server-test-{}", process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let socket = UnixDatagram::bind_addr(&addr).unwrap();
+        let mut buf = [0; 16];
+
+        send(OsStr::new(&format!("@{name}")), READY).unwrap();
+
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], READY.as_bytes());
+    }
+}
@@ -0,0 +1,246 @@
+//! TLS termination using [rustls](https://github.com/rustls/rustls).
+//!
+//! Certificates and private keys are read from PEM files. The certificate
+//! file may contain a full chain, and the private key can be encoded as
+//! PKCS#8, PKCS#1 (RSA) or SEC1 (EC).
+//!
+//! Virtual hosts with their own certificate are selected through SNI, any
+//! other client is presented the default certificate.
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+
+use crate::config::{TlsConfig, VirtualHost};
+use crate::handler::virtual_host::HostPattern;
+
+/// ALPN protocol identifier for HTTP/2
+const ALPN_H2: &[u8] = b"h2";
+
+/// ALPN protocol identifier for HTTP/1.1
+const ALPN_HTTP_1_1: &[u8] = b"http/1.1";
+
+/// Creates a `TlsAcceptor` from the certificate and key in `TlsConfig`.
+///
+/// Virtual hosts providing their own certificate and key are served such
+/// certificate when requested through SNI.
+///
+/// When `http2` is enabled, `h2` is advertised through ALPN in addition to
+/// `http/1.1`.
+pub fn make_tls_acceptor(
+    config: &TlsConfig,
+    virtual_hosts: &[VirtualHost],
+    http2: bool,
+) -> Result<TlsAcceptor> {
+    let provider = Arc::new(default_provider());
+    let resolver = SniResolver {
+        default: load_certified_key(config, &provider)?,
+        hosts: virtual_hosts
+            .iter()
+            .filter_map(|virtual_host| {
+                let tls = virtual_host.tls.as_ref()?;
+
+                Some(
+                    load_certified_key(tls, &provider).map(|key| (virtual_host.hosts.clone(), key)),
+                )
+            })
+            .collect::<Result<_>>()?,
+    };
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS protocol versions")?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+
+    server_config.alpn_protocols = if http2 {
+        vec![ALPN_H2.to_vec(), ALPN_HTTP_1_1.to_vec()]
+    } else {
+        vec![ALPN_HTTP_1_1.to_vec()]
+    };
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Selects the certificate of the virtual host matching the SNI server name,
+/// falling back to the default certificate.
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    hosts: Vec<(Vec<HostPattern>, Arc<CertifiedKey>)>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let Some(server_name) = client_hello.server_name() else {
+            return Some(Arc::clone(&self.default));
+        };
+
+        let key = self
+            .hosts
+            .iter()
+            .find(|(patterns, _)| patterns.iter().any(|pattern| pattern.matches(server_name)))
+            .map_or(&self.default, |(_, key)| key);
+
+        Some(Arc::clone(key))
+    }
+}
+
+/// Loads the certificate chain and private key in `TlsConfig`, ensuring
+/// they match.
+fn load_certified_key(config: &TlsConfig, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>> {
+    let certs = load_certs(&config.cert)?;
+    let key = load_private_key(&config.key)?;
+    let certified_key = CertifiedKey::from_der(certs, key, provider).with_context(|| {
+        format!(
+            "Invalid TLS certificate or private key: {}",
+            config.cert.display()
+        )
+    })?;
+
+    Ok(Arc::new(certified_key))
+}
+
+/// Reads every certificate from the PEM file at `path`.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .with_context(|| format!("Failed to open TLS certificate: {}", path.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse TLS certificate: {}", path.display()))?;
+
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path.display());
+    }
+
+    Ok(certs)
+}
+
+/// Reads the first private key from the PEM file at `path`.
+fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .with_context(|| format!("Failed to read TLS private key: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use http::HeaderMap;
+    use rcgen::{CertifiedKey, generate_simple_self_signed};
+    use tempfile::TempDir;
+    use tokio::io::duplex;
+    use tokio_rustls::rustls::crypto::ring::default_provider;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::make_tls_acceptor;
+    use crate::config::{Service, ServiceKind, TlsConfig, VirtualHost};
+
+    /// Writes a self-signed certificate for `name` to `dir`.
+    fn certificate(dir: &Path, name: &str) -> (TlsConfig, CertificateDer<'static>) {
+        let CertifiedKey { cert, signing_key } =
+            generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let tls = TlsConfig {
+            cert: dir.join(format!("{name}.crt")),
+            key: dir.join(format!("{name}.key")),
+        };
+
+        write(&tls.cert, cert.pem()).unwrap();
+        write(&tls.key, signing_key.serialize_pem()).unwrap();
+
+        (tls, cert.der().clone())
+    }
+
+    /// Connects to `acceptor` as `server_name`, trusting `roots`, and returns
+    /// the certificate presented by the server.
+    async fn handshake(
+        acceptor: &TlsAcceptor,
+        roots: &[CertificateDer<'static>],
+        server_name: &str,
+    ) -> CertificateDer<'static> {
+        let mut root_store = RootCertStore::empty();
+        root_store.add_parsable_certificates(roots.iter().cloned());
+
+        let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(config));
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let (client, server) = duplex(16 * 1024);
+        let (client, _server) = tokio::join!(
+            connector.connect(server_name, client),
+            acceptor.accept(server)
+        );
+        let client = client.unwrap();
+        let (_, connection) = client.get_ref();
+
+        connection.peer_certificates().unwrap()[0].clone()
+    }
+
+    #[tokio::test]
+    async fn selects_certificate_through_sni() {
+        let dir = TempDir::new().unwrap();
+        let (default_tls, default_cert) = certificate(dir.path(), "localhost");
+        let (host_tls, host_cert) = certificate(dir.path(), "example.com");
+        let virtual_host = VirtualHost {
+            hosts: vec!["example.com".parse().unwrap()],
+            service: Service::new(ServiceKind::FileServer, None, None, None).unwrap(),
+            headers: HeaderMap::new(),
+            tls: Some(host_tls),
+        };
+        let acceptor = make_tls_acceptor(&default_tls, &[virtual_host], false).unwrap();
+        let roots = [default_cert.clone(), host_cert.clone()];
+
+        assert_eq!(handshake(&acceptor, &roots, "example.com").await, host_cert);
+        assert_eq!(
+            handshake(&acceptor, &roots, "localhost").await,
+            default_cert
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_default_certificate() {
+        let dir = TempDir::new().unwrap();
+        let (default_tls, default_cert) = certificate(dir.path(), "unknown.test");
+        let (host_tls, host_cert) = certificate(dir.path(), "example.com");
+        let virtual_host = VirtualHost {
+            hosts: vec!["example.com".parse().unwrap()],
+            service: Service::new(ServiceKind::FileServer, None, None, None).unwrap(),
+            headers: HeaderMap::new(),
+            tls: Some(host_tls),
+        };
+        let acceptor = make_tls_acceptor(&default_tls, &[virtual_host], false).unwrap();
+        let roots = [default_cert.clone(), host_cert];
+
+        // Server names matching no virtual host are served the default one
+        assert_eq!(
+            handshake(&acceptor, &roots, "unknown.test").await,
+            default_cert
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_keys() {
+        let dir = TempDir::new().unwrap();
+        let (tls, _) = certificate(dir.path(), "localhost");
+        let (other, _) = certificate(dir.path(), "example.com");
+        let tls = TlsConfig {
+            cert: tls.cert,
+            key: other.key,
+        };
+
+        assert!(make_tls_acceptor(&tls, &[], false).is_err());
+    }
+}